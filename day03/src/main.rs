@@ -1,27 +1,181 @@
-use std::collections::hash_map::RandomState;
-use std::collections::HashSet;
+use std::fmt;
 
 fn main() {
     let input = include_str!("../input.txt");
-    println!("{}", part_1(parse_rucksack_into_compartments(input)));
-    println!("{}", part_2(parse_elf_groups(input)));
+    // Optional arguments: group size and whether any grouping of elves may be used
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let group_size = match args.iter().find(|arg| !arg.starts_with("--")) {
+        Some(size) => match size.parse() {
+            Ok(size) => size,
+            Err(_) => return println!("Group size must be a number, not {:?}", size),
+        },
+        None => 3,
+    };
+    match parse_rucksack_into_compartments(input).and_then(part_1) {
+        Ok(score) => println!("{}", score),
+        Err(error) => println!("{}", error),
+    }
+    let part_2_score = if args.iter().any(|arg| arg == "--any-grouping") {
+        part_2_any_grouping(input, group_size)
+            .map(|score| score.map_or("No grouping found".to_string(), |s| s.to_string()))
+    } else {
+        parse_elf_groups(input, group_size)
+            .and_then(part_2)
+            .map(|score| score.to_string())
+    };
+    match part_2_score {
+        Ok(score) => println!("{}", score),
+        Err(error) => println!("{}", error),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct ItemSet(u64);
+
+impl ItemSet {
+    // Every item type gets the bit of its priority, so bits 1 to 52 are used
+    fn from_items(items: &str) -> Result<ItemSet, RucksackError> {
+        items.chars().try_fold(ItemSet::default(), |set, item| {
+            if item.is_ascii_alphabetic() {
+                Ok(ItemSet(set.0 | 1 << get_score_for_char(item)))
+            } else {
+                Err(RucksackError::InvalidItem(item))
+            }
+        })
+    }
+
+    fn intersection(self, other: ItemSet) -> ItemSet {
+        ItemSet(self.0 & other.0)
+    }
+
+    fn len(self) -> u32 {
+        self.0.count_ones()
+    }
+
+    fn items(self) -> Vec<char> {
+        (1..=52)
+            .filter(|priority| self.0 & 1 << priority != 0)
+            .map(get_char_for_score)
+            .collect()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum RucksackError {
+    InvalidItem(char),
+    UnevenRucksack {
+        rucksack: usize,
+        items: usize,
+    },
+    NoCommonItem {
+        rucksack: usize,
+    },
+    SeveralCommonItems {
+        rucksack: usize,
+        items: Vec<char>,
+    },
+    InvalidGroupSize,
+    IncompleteGroup {
+        group: usize,
+        elves: usize,
+        group_size: usize,
+    },
+    NoBadge {
+        group: usize,
+    },
+    SeveralBadges {
+        group: usize,
+        items: Vec<char>,
+    },
+    SearchLimitReached {
+        limit: usize,
+    },
+}
+
+impl fmt::Display for RucksackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RucksackError::InvalidItem(item) => write!(f, "Invalid item: {:?}", item),
+            RucksackError::UnevenRucksack { rucksack, items } => write!(
+                f,
+                "Rucksack {} has {} items, which do not split into two equal compartments",
+                rucksack, items
+            ),
+            RucksackError::NoCommonItem { rucksack } => {
+                write!(f, "Rucksack {} has no item in both compartments", rucksack)
+            }
+            RucksackError::SeveralCommonItems { rucksack, items } => write!(
+                f,
+                "Rucksack {} has several items in both compartments: {}",
+                rucksack,
+                items.iter().collect::<String>()
+            ),
+            RucksackError::InvalidGroupSize => write!(f, "Groups need at least one elf"),
+            RucksackError::IncompleteGroup {
+                group,
+                elves,
+                group_size,
+            } => write!(
+                f,
+                "Group {} only has {} of {} elves",
+                group, elves, group_size
+            ),
+            RucksackError::NoBadge { group } => write!(f, "Group {} has no common badge", group),
+            RucksackError::SeveralBadges { group, items } => write!(
+                f,
+                "Group {} has several common badges: {}",
+                group,
+                items.iter().collect::<String>()
+            ),
+            RucksackError::SearchLimitReached { limit } => write!(
+                f,
+                "Gave up looking for a badge grouping after trying {} elves",
+                limit
+            ),
+        }
+    }
 }
 
-fn parse_rucksack_into_compartments(input: &str) -> Vec<(String, String)> {
+fn parse_rucksack_into_compartments(input: &str) -> Result<Vec<(String, String)>, RucksackError> {
     input
         .split('\n')
-        .map(|rucksack| rucksack.split_at(rucksack.len() / 2))
-        .map(|(a, b)| (String::from(a), String::from(b)))
+        .enumerate()
+        .map(|(index, rucksack)| {
+            // Items are validated first, so the split lands between two ASCII letters
+            if let Some(item) = rucksack.chars().find(|item| !item.is_ascii_alphabetic()) {
+                return Err(RucksackError::InvalidItem(item));
+            }
+            if rucksack.len() % 2 != 0 {
+                return Err(RucksackError::UnevenRucksack {
+                    rucksack: index,
+                    items: rucksack.len(),
+                });
+            }
+            let (a, b) = rucksack.split_at(rucksack.len() / 2);
+            Ok((String::from(a), String::from(b)))
+        })
         .collect()
 }
 
-fn get_common_letters(rucksack: Vec<(String, String)>) -> Vec<char> {
+fn get_common_item_set(rucksack: &(String, String)) -> Result<ItemSet, RucksackError> {
+    let (c1, c2) = rucksack;
+    Ok(ItemSet::from_items(c1)?.intersection(ItemSet::from_items(c2)?))
+}
+
+fn get_common_letters(rucksack: Vec<(String, String)>) -> Result<Vec<char>, RucksackError> {
     rucksack
         .iter()
-        .map(|(c1, c2)| {
-            let s1: HashSet<char, RandomState> = c1.chars().into_iter().collect();
-            let s2: HashSet<char, _> = c2.chars().into_iter().collect();
-            s1.intersection(&s2).copied().collect::<Vec<char>>()[0]
+        .enumerate()
+        .map(|(index, compartments)| {
+            let common = get_common_item_set(compartments)?;
+            match common.len() {
+                0 => Err(RucksackError::NoCommonItem { rucksack: index }),
+                1 => Ok(common.items()[0]),
+                _ => Err(RucksackError::SeveralCommonItems {
+                    rucksack: index,
+                    items: common.items(),
+                }),
+            }
         })
         .collect()
 }
@@ -34,50 +188,329 @@ fn get_score_for_char(c: char) -> u32 {
     }
 }
 
-fn part_1(rucksack: Vec<(String, String)>) -> u32 {
-    get_common_letters(rucksack)
+fn get_char_for_score(score: u32) -> char {
+    if score <= 26 {
+        (score + 96) as u8 as char
+    } else {
+        (score + 38) as u8 as char
+    }
+}
+
+fn part_1(rucksack: Vec<(String, String)>) -> Result<u32, RucksackError> {
+    Ok(get_common_letters(rucksack)?
         .into_iter()
         .map(get_score_for_char)
-        .sum()
+        .sum())
 }
 
-fn parse_elf_groups(input: &str) -> Vec<(String, String, String)> {
+fn parse_elf_groups(input: &str, group_size: usize) -> Result<Vec<Vec<String>>, RucksackError> {
+    if group_size == 0 {
+        return Err(RucksackError::InvalidGroupSize);
+    }
     input
         .split('\n')
-        .into_iter()
         .collect::<Vec<&str>>()
-        .as_slice()
-        .chunks(3)
-        .map(|c| (c[0], c[1], c[2]))
-        .map(|(a, b, c)| (a.to_string(), b.to_string(), c.to_string()))
+        .chunks(group_size)
+        .enumerate()
+        .map(|(index, chunk)| {
+            if chunk.len() == group_size {
+                Ok(chunk.iter().map(|elf| elf.to_string()).collect())
+            } else {
+                Err(RucksackError::IncompleteGroup {
+                    group: index,
+                    elves: chunk.len(),
+                    group_size,
+                })
+            }
+        })
         .collect()
 }
 
-fn get_common_letter_in_group(group: (String, String, String)) -> char {
-    let (c1, c2, c3) = group;
-    let s1: HashSet<char, RandomState> = c1.chars().into_iter().collect();
-    let s2: HashSet<char, RandomState> = c2.chars().into_iter().collect();
-    let s3: HashSet<char, RandomState> = c3.chars().into_iter().collect();
-    s1.intersection(&s2)
-        .cloned()
-        .collect::<HashSet<char>>()
-        .intersection(&s3)
-        .copied()
-        .collect::<Vec<char>>()[0]
+fn get_common_letter_in_group(index: usize, group: &[String]) -> Result<char, RucksackError> {
+    let common = group.iter().try_fold(ItemSet(u64::MAX), |common, elf| {
+        Ok(common.intersection(ItemSet::from_items(elf)?))
+    })?;
+    match common.len() {
+        0 => Err(RucksackError::NoBadge { group: index }),
+        1 => Ok(common.items()[0]),
+        _ => Err(RucksackError::SeveralBadges {
+            group: index,
+            items: common.items(),
+        }),
+    }
 }
 
-fn part_2(groups: Vec<(String, String, String)>) -> u32 {
+fn part_2(groups: Vec<Vec<String>>) -> Result<u32, RucksackError> {
     groups
-        .into_iter()
-        .map(get_common_letter_in_group)
-        .map(get_score_for_char)
+        .iter()
+        .enumerate()
+        .map(|(index, group)| get_common_letter_in_group(index, group).map(get_score_for_char))
         .sum()
 }
 
+// How many elves the badge grouping search may try before giving up
+const SEARCH_LIMIT: usize = 10_000_000;
+
+// Instead of grouping consecutive elves, search for any grouping in which every group has exactly one badge.
+// Returns the groups as line indices.
+// This is a backtracking search, which takes exponential time in the worst case, for example when
+// many elves share items but no grouping works out, so it stops after trying `limit` elves.
+fn find_badge_grouping(
+    input: &str,
+    group_size: usize,
+    limit: usize,
+) -> Result<Option<Vec<Vec<usize>>>, RucksackError> {
+    let elves = input
+        .split('\n')
+        .map(ItemSet::from_items)
+        .collect::<Result<Vec<ItemSet>, RucksackError>>()?;
+    if group_size == 0 {
+        return Err(RucksackError::InvalidGroupSize);
+    }
+    if elves.len() % group_size != 0 {
+        return Err(RucksackError::IncompleteGroup {
+            group: elves.len() / group_size,
+            elves: elves.len() % group_size,
+            group_size,
+        });
+    }
+    let mut search = BadgeSearch {
+        assigned: vec![false; elves.len()],
+        elves,
+        group_size,
+        groups: Vec::new(),
+        remaining_steps: limit,
+    };
+    if search.search() {
+        Ok(Some(search.groups))
+    } else if search.remaining_steps == 0 {
+        Err(RucksackError::SearchLimitReached { limit })
+    } else {
+        Ok(None)
+    }
+}
+
+fn part_2_any_grouping(input: &str, group_size: usize) -> Result<Option<u32>, RucksackError> {
+    let lines = input.split('\n').collect::<Vec<&str>>();
+    match find_badge_grouping(input, group_size, SEARCH_LIMIT)? {
+        Some(grouping) => {
+            let groups = grouping
+                .iter()
+                .map(|group| group.iter().map(|&elf| lines[elf].to_string()).collect())
+                .collect();
+            part_2(groups).map(Some)
+        }
+        None => Ok(None),
+    }
+}
+
+struct BadgeSearch {
+    elves: Vec<ItemSet>,
+    group_size: usize,
+    assigned: Vec<bool>,
+    groups: Vec<Vec<usize>>,
+    remaining_steps: usize,
+}
+
+impl BadgeSearch {
+    fn search(&mut self) -> bool {
+        // The first free elf has to be in some group, so only groups containing it are tried
+        let first = match self.assigned.iter().position(|a| !a) {
+            Some(first) => first,
+            None => return true,
+        };
+        self.assigned[first] = true;
+        let found = self.extend_group(&mut vec![first], self.elves[first]);
+        self.assigned[first] = false;
+        found
+    }
+
+    fn extend_group(&mut self, group: &mut Vec<usize>, common: ItemSet) -> bool {
+        if group.len() == self.group_size {
+            if common.len() != 1 {
+                return false;
+            }
+            self.groups.push(group.clone());
+            if self.search() {
+                return true;
+            }
+            self.groups.pop();
+            return false;
+        }
+        for elf in *group.last().unwrap() + 1..self.elves.len() {
+            let next_common = common.intersection(self.elves[elf]);
+            if self.assigned[elf] || next_common.len() == 0 {
+                continue;
+            }
+            if self.remaining_steps == 0 {
+                return false;
+            }
+            self.remaining_steps -= 1;
+            self.assigned[elf] = true;
+            group.push(elf);
+            let found = self.extend_group(group, next_common);
+            group.pop();
+            self.assigned[elf] = false;
+            if found {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+// Deterministic rucksacks with exactly one shared item, so no rand dependency is needed
+#[cfg(test)]
+fn generate_rucksacks(count: usize) -> String {
+    let mut state: u64 = 0x2022_1203;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    (0..count)
+        .map(|_| {
+            let shared = 1 + next() % 52;
+            let half_len = 8 + (next() % 16) as usize;
+            let mut first = String::new();
+            let mut second = String::new();
+            first.push(get_char_for_score(shared as u32));
+            second.push(get_char_for_score(shared as u32));
+            // Lower half of the remaining priorities goes left, upper half goes right
+            let left = (1..=52).filter(|p| *p != shared).collect::<Vec<u64>>();
+            let (left, right) = left.split_at(left.len() / 2);
+            while first.len() < half_len {
+                first.push(get_char_for_score(
+                    left[next() as usize % left.len()] as u32,
+                ));
+                second.push(get_char_for_score(
+                    right[next() as usize % right.len()] as u32,
+                ));
+            }
+            first + &second
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
 #[test]
 fn test_example() {
     let input = include_str!("../example.txt");
 
-    assert_eq!(157, part_1(parse_rucksack_into_compartments(input)));
-    assert_eq!(70, part_2(parse_elf_groups(input)));
+    assert_eq!(
+        Ok(157),
+        parse_rucksack_into_compartments(input).and_then(part_1)
+    );
+    assert_eq!(Ok(70), parse_elf_groups(input, 3).and_then(part_2));
+}
+
+#[test]
+fn test_irregular_rucksacks() {
+    assert_eq!(
+        Err(RucksackError::NoCommonItem { rucksack: 1 }),
+        parse_rucksack_into_compartments("abca\nabcd").and_then(part_1)
+    );
+    assert_eq!(
+        Err(RucksackError::SeveralCommonItems {
+            rucksack: 0,
+            items: vec!['a', 'b']
+        }),
+        parse_rucksack_into_compartments("abab").and_then(part_1)
+    );
+    assert_eq!(
+        Err(RucksackError::InvalidItem('1')),
+        parse_rucksack_into_compartments("a1a1").and_then(part_1)
+    );
+    assert_eq!(
+        Err(RucksackError::InvalidItem('é')),
+        parse_rucksack_into_compartments("ab\néa")
+    );
+    assert_eq!(
+        Err(RucksackError::UnevenRucksack {
+            rucksack: 1,
+            items: 3
+        }),
+        parse_rucksack_into_compartments("abca\naba")
+    );
+}
+
+#[test]
+fn test_group_sizes() {
+    let input = include_str!("../example.txt");
+
+    assert_eq!(
+        Err(RucksackError::IncompleteGroup {
+            group: 1,
+            elves: 2,
+            group_size: 4
+        }),
+        parse_elf_groups(input, 4)
+    );
+    // Pairs of consecutive elves share more than one item type
+    assert_eq!(
+        Err(RucksackError::SeveralBadges {
+            group: 0,
+            items: vec!['f', 'r', 's', 'F', 'M']
+        }),
+        parse_elf_groups(input, 2).and_then(part_2)
+    );
+    assert_eq!(Ok(52), parse_elf_groups("Zz\nZy", 2).and_then(part_2));
+}
+
+#[test]
+fn test_badge_grouping() {
+    let input = include_str!("../example.txt");
+    assert_eq!(
+        Ok(Some(vec![vec![0, 1, 2], vec![3, 4, 5]])),
+        find_badge_grouping(input, 3, SEARCH_LIMIT)
+    );
+
+    // Consecutive pairs have no single badge, but pairing the first and last elves does
+    let input = "ab\nab\nbc\nac";
+    assert_eq!(
+        Err(RucksackError::SeveralBadges {
+            group: 0,
+            items: vec!['a', 'b']
+        }),
+        parse_elf_groups(input, 2).and_then(part_2)
+    );
+    assert_eq!(
+        Ok(Some(vec![vec![0, 2], vec![1, 3]])),
+        find_badge_grouping(input, 2, SEARCH_LIMIT)
+    );
+    assert_eq!(
+        Ok(Some(70)),
+        part_2_any_grouping(include_str!("../example.txt"), 3)
+    );
+    assert_eq!(Ok(None), find_badge_grouping("ab\nab", 2, SEARCH_LIMIT));
+    assert_eq!(
+        Err(RucksackError::InvalidGroupSize),
+        find_badge_grouping("ab", 0, SEARCH_LIMIT)
+    );
+
+    // Every pairing of the first elves is tried before the last two are found to have no badge
+    let input = ["a"; 22].join("\n") + "\nb\nc";
+    assert_eq!(
+        Err(RucksackError::SearchLimitReached { limit: 1000 }),
+        find_badge_grouping(&input, 2, 1000)
+    );
+}
+
+#[test]
+#[ignore]
+fn bench_common_letters() {
+    let input = generate_rucksacks(1_000_000);
+    let rucksacks = parse_rucksack_into_compartments(&input).unwrap();
+    let start = std::time::Instant::now();
+    let score = part_1(rucksacks).unwrap();
+    let elapsed = start.elapsed();
+    println!(
+        "{} rucksacks ({} bytes) in {:?}, {:.1} MB/s, score {}",
+        1_000_000,
+        input.len(),
+        elapsed,
+        input.len() as f64 / elapsed.as_secs_f64() / 1e6,
+        score
+    );
 }
@@ -1,52 +1,289 @@
 use regex::Regex;
+use std::fmt;
 
 fn main() {
     let input = include_str!("../input.txt");
     println!("{}", part_1(input));
     println!("{}", part_2(input));
+
+    let assignments = camp_assignments(&parse_ranges(input));
+    let uncovered = uncovered_sections(&assignments);
+    println!("Uncovered sections: {} ({})", uncovered, uncovered.len());
+    if let Some((section, elves)) = most_covered_section(&assignments) {
+        println!("Most covered section: {} ({} elves)", section, elves);
+    }
+    println!(
+        "Conflicting pairs: {}",
+        conflicting_pairs(&assignments).len()
+    );
+}
+
+// Inclusive range of section IDs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Interval {
+    start: u32,
+    end: u32,
+}
+
+impl Interval {
+    // A range written backwards, like 5-3, covers the same sections as 3-5
+    fn new(a: u32, b: u32) -> Interval {
+        Interval {
+            start: a.min(b),
+            end: a.max(b),
+        }
+    }
+
+    fn contains(&self, other: &Interval) -> bool {
+        self.start <= other.start && self.end >= other.end
+    }
+
+    fn overlaps(&self, other: &Interval) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+
+    fn len(&self) -> u64 {
+        (self.end - self.start) as u64 + 1
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
 }
 
-fn parse_ranges(input: &str) -> Vec<(u32, u32, u32, u32)> {
-    let re = Regex::new(r"([0-9]+)-([0-9]+),([0-9]+)-([0-9]+)").unwrap();
+// Sorted, disjoint and non-adjacent intervals
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+struct IntervalSet {
+    intervals: Vec<Interval>,
+}
+
+// Not every set operation is needed by the puzzle itself
+#[allow(dead_code)]
+impl IntervalSet {
+    fn from_intervals(intervals: impl IntoIterator<Item = Interval>) -> IntervalSet {
+        let mut sorted = intervals.into_iter().collect::<Vec<Interval>>();
+        sorted.sort();
+        let mut merged: Vec<Interval> = Vec::new();
+        for interval in sorted {
+            match merged.last_mut() {
+                Some(last) if interval.start <= last.end.saturating_add(1) => {
+                    last.end = last.end.max(interval.end)
+                }
+                _ => merged.push(interval),
+            }
+        }
+        IntervalSet { intervals: merged }
+    }
+
+    fn union(&self, other: &IntervalSet) -> IntervalSet {
+        IntervalSet::from_intervals(self.intervals.iter().chain(&other.intervals).copied())
+    }
+
+    fn intersection(&self, other: &IntervalSet) -> IntervalSet {
+        let mut result = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < self.intervals.len() && j < other.intervals.len() {
+            let (a, b) = (self.intervals[i], other.intervals[j]);
+            if a.overlaps(&b) {
+                result.push(Interval::new(a.start.max(b.start), a.end.min(b.end)));
+            }
+            if a.end < b.end {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        IntervalSet { intervals: result }
+    }
+
+    fn difference(&self, other: &IntervalSet) -> IntervalSet {
+        let mut result = Vec::new();
+        let mut j = 0;
+        for interval in &self.intervals {
+            // In u64, as the section after the remaining part can be past u32::MAX
+            let mut start = interval.start as u64;
+            // Skip the removed intervals that end before this one
+            while j < other.intervals.len() && (other.intervals[j].end as u64) < start {
+                j += 1;
+            }
+            let mut k = j;
+            while k < other.intervals.len() && other.intervals[k].start <= interval.end {
+                let removed = other.intervals[k];
+                if removed.start as u64 > start {
+                    result.push(Interval::new(start as u32, removed.start - 1));
+                }
+                if removed.end >= interval.end {
+                    start = interval.end as u64 + 1;
+                    break;
+                }
+                start = removed.end as u64 + 1;
+                k += 1;
+            }
+            if start <= interval.end as u64 {
+                result.push(Interval::new(start as u32, interval.end));
+            }
+        }
+        IntervalSet { intervals: result }
+    }
+
+    fn contains_section(&self, section: u32) -> bool {
+        self.contains_interval(&Interval::new(section, section))
+    }
+
+    fn contains_interval(&self, interval: &Interval) -> bool {
+        // Intervals are merged, so a contained interval lies within a single one of them
+        let index = self
+            .intervals
+            .partition_point(|candidate| candidate.end < interval.start);
+        self.intervals
+            .get(index)
+            .is_some_and(|candidate| candidate.contains(interval))
+    }
+
+    fn is_superset(&self, other: &IntervalSet) -> bool {
+        other
+            .intervals
+            .iter()
+            .all(|interval| self.contains_interval(interval))
+    }
+
+    fn len(&self) -> u64 {
+        self.intervals.iter().map(Interval::len).sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    fn span(&self) -> Option<Interval> {
+        Some(Interval::new(
+            self.intervals.first()?.start,
+            self.intervals.last()?.end,
+        ))
+    }
+}
+
+impl fmt::Display for IntervalSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let intervals = self
+            .intervals
+            .iter()
+            .map(|interval| interval.to_string())
+            .collect::<Vec<String>>();
+        write!(f, "{{{}}}", intervals.join(", "))
+    }
+}
+
+fn parse_ranges(input: &str) -> Vec<Vec<Interval>> {
+    let re = Regex::new(r"^([0-9]+)-([0-9]+)$").unwrap();
     input
         .split('\n')
         .map(|line| {
-            let caps = re.captures(line).unwrap();
-            (
-                caps.get(1)
-                    .map_or(0, |m| m.as_str().parse::<u32>().unwrap()),
-                caps.get(2)
-                    .map_or(0, |m| m.as_str().parse::<u32>().unwrap()),
-                caps.get(3)
-                    .map_or(0, |m| m.as_str().parse::<u32>().unwrap()),
-                caps.get(4)
-                    .map_or(0, |m| m.as_str().parse::<u32>().unwrap()),
-            )
+            line.split(',')
+                .map(|range| {
+                    let caps = re
+                        .captures(range.trim())
+                        .unwrap_or_else(|| panic!("Invalid range: {}", range));
+                    Interval::new(caps[1].parse().unwrap(), caps[2].parse().unwrap())
+                })
+                .collect()
         })
         .collect()
 }
 
-fn range_fully_contained(ranges: (u32, u32, u32, u32)) -> bool {
-    let (a, b, c, d) = ranges;
-    (a <= c && b >= d) || (c <= a && d >= b)
+// Entry [i][j] tells whether range i fully contains range j
+fn containment_matrix(ranges: &[Interval]) -> Vec<Vec<bool>> {
+    ranges
+        .iter()
+        .map(|a| ranges.iter().map(|b| a.contains(b)).collect())
+        .collect()
+}
+
+// Entry [i][j] tells whether ranges i and j share a section
+fn overlap_matrix(ranges: &[Interval]) -> Vec<Vec<bool>> {
+    ranges
+        .iter()
+        .map(|a| ranges.iter().map(|b| a.overlaps(b)).collect())
+        .collect()
+}
+
+// Index of a range that contains all other ranges of the line
+fn covering_range(ranges: &[Interval]) -> Option<usize> {
+    containment_matrix(ranges)
+        .iter()
+        .position(|row| row.iter().all(|&contains| contains))
+}
+
+fn has_overlap(ranges: &[Interval]) -> bool {
+    overlap_matrix(ranges).iter().enumerate().any(|(i, row)| {
+        row.iter()
+            .enumerate()
+            .any(|(j, &overlaps)| i != j && overlaps)
+    })
+}
+
+// All assignments of the camp in reading order, elf i is the i-th range in the input
+fn camp_assignments(lines: &[Vec<Interval>]) -> Vec<Interval> {
+    lines.iter().flatten().copied().collect()
+}
+
+// Sections between the lowest and highest assigned section that nobody cleans
+fn uncovered_sections(assignments: &[Interval]) -> IntervalSet {
+    let covered = IntervalSet::from_intervals(assignments.iter().copied());
+    match covered.span() {
+        Some(span) => IntervalSet::from_intervals([span]).difference(&covered),
+        None => IntervalSet::default(),
+    }
 }
 
-fn range_overlaps(ranges: (u32, u32, u32, u32)) -> bool {
-    let (a, b, c, d) = ranges;
-    (b >= c && a <= c) || (c <= a && d >= a) || range_fully_contained(ranges)
+// The lowest section with the most assigned elves, along with the number of elves
+fn most_covered_section(assignments: &[Interval]) -> Option<(u32, usize)> {
+    let mut events = assignments
+        .iter()
+        .flat_map(|interval| [(interval.start as u64, 1), (interval.end as u64 + 1, -1)])
+        .collect::<Vec<(u64, i64)>>();
+    // Ends sort before starts at the same position
+    events.sort();
+    let mut covering = 0;
+    let mut best: Option<(u32, usize)> = None;
+    for (position, change) in events {
+        covering += change;
+        if best.is_none_or(|(_, elves)| covering as usize > elves) {
+            best = Some((position as u32, covering as usize));
+        }
+    }
+    best
+}
+
+// Every pair of elves (as indices into the assignments) whose assignments overlap
+fn conflicting_pairs(assignments: &[Interval]) -> Vec<(usize, usize)> {
+    let mut order = (0..assignments.len()).collect::<Vec<usize>>();
+    order.sort_by_key(|&elf| assignments[elf]);
+    let mut active: Vec<usize> = Vec::new();
+    let mut pairs = Vec::new();
+    for elf in order {
+        let interval = assignments[elf];
+        active.retain(|&other| assignments[other].end >= interval.start);
+        pairs.extend(active.iter().map(|&other| (other.min(elf), other.max(elf))));
+        active.push(elf);
+    }
+    pairs.sort();
+    pairs
 }
 
 fn part_1(input: &str) -> usize {
     parse_ranges(input)
-        .into_iter()
-        .filter(|(a, b, c, d)| range_fully_contained((*a, *b, *c, *d)))
+        .iter()
+        .filter(|ranges| ranges.len() > 1 && covering_range(ranges).is_some())
         .count()
 }
 
 fn part_2(input: &str) -> usize {
     parse_ranges(input)
-        .into_iter()
-        .filter(|(a, b, c, d)| range_overlaps((*a, *b, *c, *d)))
+        .iter()
+        .filter(|ranges| has_overlap(ranges))
         .count()
 }
 
@@ -57,3 +294,115 @@ fn test_example() {
     assert_eq!(2, part_1(input));
     assert_eq!(4, part_2(input));
 }
+
+#[test]
+fn test_interval_set() {
+    let a = IntervalSet::from_intervals([Interval::new(1, 5), Interval::new(10, 20)]);
+    let b = IntervalSet::from_intervals([Interval::new(4, 12), Interval::new(6, 6)]);
+
+    assert_eq!(
+        IntervalSet::from_intervals([Interval::new(1, 20)]),
+        a.union(&b)
+    );
+    assert_eq!(
+        IntervalSet::from_intervals([Interval::new(4, 5), Interval::new(10, 12)]),
+        a.intersection(&b)
+    );
+    assert_eq!(
+        IntervalSet::from_intervals([Interval::new(1, 3), Interval::new(13, 20)]),
+        a.difference(&b)
+    );
+    assert_eq!(
+        IntervalSet::from_intervals([Interval::new(6, 9)]),
+        b.difference(&a)
+    );
+    assert!(a.contains_section(15));
+    assert!(!a.contains_section(7));
+    assert!(a.contains_interval(&Interval::new(10, 20)));
+    assert!(!a.contains_interval(&Interval::new(4, 10)));
+    assert!(a.union(&b).is_superset(&a));
+    assert!(!a.is_superset(&b));
+    assert_eq!(16, a.len());
+    assert!(a.difference(&a).is_empty());
+    // Adjacent intervals merge
+    assert_eq!(
+        IntervalSet::from_intervals([Interval::new(1, 9)]),
+        IntervalSet::from_intervals([Interval::new(1, 4), Interval::new(5, 9)])
+    );
+}
+
+#[test]
+fn test_camp_queries() {
+    let input = include_str!("../example.txt");
+    let assignments = camp_assignments(&parse_ranges(input));
+
+    assert!(uncovered_sections(&assignments).is_empty());
+    assert_eq!(
+        IntervalSet::from_intervals([Interval::new(4, 5)]),
+        uncovered_sections(&[Interval::new(1, 3), Interval::new(6, 9)])
+    );
+    // Sections up to u32::MAX are counted without overflowing
+    let whole = camp_assignments(&parse_ranges("0-4294967295,5-6"));
+    assert!(uncovered_sections(&whole).is_empty());
+    assert_eq!(1 << 32, IntervalSet::from_intervals(whole).len());
+    assert_eq!(
+        IntervalSet::from_intervals([Interval::new(u32::MAX, u32::MAX)]),
+        IntervalSet::from_intervals([Interval::new(0, u32::MAX)]).difference(
+            &IntervalSet::from_intervals([Interval::new(0, u32::MAX - 1)])
+        )
+    );
+    assert_eq!(Some((6, 8)), most_covered_section(&assignments));
+    assert_eq!(None, most_covered_section(&[]));
+    assert_eq!(
+        vec![(0, 1), (0, 2)],
+        conflicting_pairs(&[
+            Interval::new(1, 5),
+            Interval::new(3, 4),
+            Interval::new(5, 8)
+        ])
+    );
+    // Every overlapping pair from part 2 is among the conflicts of the whole camp
+    let conflicts = conflicting_pairs(&assignments);
+    for (line, ranges) in parse_ranges(input).iter().enumerate() {
+        assert_eq!(
+            has_overlap(ranges),
+            conflicts.contains(&(2 * line, 2 * line + 1))
+        );
+    }
+}
+
+#[test]
+fn test_more_elves_per_line() {
+    let lines = parse_ranges("2-8,3-7,4-4\n1-2, 3-4,2-3\n1-1\n1-3,5-6");
+
+    assert_eq!(
+        vec![
+            vec![true, true, true],
+            vec![false, true, true],
+            vec![false, false, true]
+        ],
+        containment_matrix(&lines[0])
+    );
+    assert_eq!(
+        vec![
+            vec![true, false, true],
+            vec![false, true, true],
+            vec![true, true, true]
+        ],
+        overlap_matrix(&lines[1])
+    );
+    assert_eq!(Some(0), covering_range(&lines[0]));
+    assert_eq!(None, covering_range(&lines[1]));
+    assert_eq!(Some(0), covering_range(&lines[2]));
+    assert!(has_overlap(&lines[1]));
+    assert!(!has_overlap(&lines[2]));
+    assert!(!has_overlap(&lines[3]));
+
+    // Backwards ranges are read the right way round
+    assert_eq!(vec![vec![Interval::new(3, 5)]], parse_ranges("5-3"));
+    assert_eq!(1, part_1("8-2,3-7"));
+
+    // A single elf does not count as a fully contained pair
+    assert_eq!(1, part_1("2-8,3-7,4-4\n1-2,3-4,2-3\n1-1\n1-3,5-6"));
+    assert_eq!(2, part_2("2-8,3-7,4-4\n1-2,3-4,2-3\n1-1\n1-3,5-6"));
+}
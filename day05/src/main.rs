@@ -1,9 +1,22 @@
 use regex::Regex;
+use std::fmt;
 
 fn main() {
     let input = include_str!("../input.txt");
-    println!("{}", part_1(input).as_str());
-    println!("{}", part_2(input).as_str());
+    let (stacks, commands) = parse_input(input);
+    if let Err(error) = dry_run(&stacks, &commands) {
+        println!("{}", error);
+        return;
+    }
+    if std::env::args().any(|arg| arg == "--draw") {
+        simulate(CrateMover9000, input, true).unwrap();
+        simulate(CrateMover9001, input, true).unwrap();
+    }
+    if std::env::args().any(|arg| arg == "--replay") {
+        print_replay(&stacks, simulate(CrateMover9001, input, false).unwrap());
+    }
+    println!("{}", part_1(input).unwrap().as_str());
+    println!("{}", part_2(input).unwrap().as_str());
 }
 
 struct Command {
@@ -12,44 +25,145 @@ struct Command {
     to: usize,
 }
 
-fn parse_input(input: &str) -> (Vec<Vec<char>>, Vec<Command>) {
-    let split = input
-        .split("\n\n")
-        .map(|s| s.to_string())
-        .collect::<Vec<String>>();
-    let initial_stack_layout = split[0].to_string();
-    let stack_count: usize = (initial_stack_layout
-        .split('\n')
-        .map(|s| s.to_string())
-        .collect::<Vec<String>>()[0]
-        .len()
-        / 3)
-        + 1;
-    let mut stacks = (0..stack_count).map(|_| vec![]).collect::<Vec<Vec<char>>>();
-    let initial_stack_layout_len = initial_stack_layout.split('\n').count();
-    initial_stack_layout
-        .split('\n')
-        .enumerate()
-        .for_each(|(i, line)| {
-            if i < initial_stack_layout_len - 1 {
-                line.to_string()
-                    .chars()
-                    .collect::<Vec<char>>()
-                    .as_slice()
-                    .chunks(4)
-                    .enumerate()
-                    .for_each(|(i, stack_content)| {
-                        if stack_content.iter().collect::<String>() != *"    " {
-                            stacks[i]
-                                .push((*stack_content).iter().copied().collect::<Vec<char>>()[1])
-                        }
-                    })
+impl Command {
+    // Checks the command against the current stack heights
+    fn validate(&self, index: usize, heights: &[usize]) -> Result<(), MoveError> {
+        let error = |kind| MoveError {
+            command: index,
+            from: self.from,
+            to: self.to,
+            kind,
+        };
+        for stack in [self.from, self.to] {
+            if stack == 0 || stack > heights.len() {
+                return Err(error(MoveErrorKind::UnknownStack {
+                    stack,
+                    stack_count: heights.len(),
+                }));
             }
-        });
+        }
+        let available = heights[self.from - 1];
+        if self.amount as usize > available {
+            return Err(error(MoveErrorKind::NotEnoughCrates {
+                requested: self.amount as usize,
+                available,
+            }));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct MoveError {
+    command: usize,
+    from: usize,
+    to: usize,
+    kind: MoveErrorKind,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum MoveErrorKind {
+    UnknownStack { stack: usize, stack_count: usize },
+    NotEnoughCrates { requested: usize, available: usize },
+    InvalidLifts { amount: usize, lifted: usize },
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Command {} (from {} to {}): ",
+            self.command, self.from, self.to
+        )?;
+        match self.kind {
+            MoveErrorKind::UnknownStack { stack, stack_count } => {
+                write!(
+                    f,
+                    "stack {} does not exist, there are {} stacks",
+                    stack, stack_count
+                )
+            }
+            MoveErrorKind::NotEnoughCrates {
+                requested,
+                available,
+            } => write!(
+                f,
+                "cannot move {} crates, only {} available",
+                requested, available
+            ),
+            MoveErrorKind::InvalidLifts { amount, lifted } => write!(
+                f,
+                "the crane lifts {} crates in total to move {}",
+                lifted, amount
+            ),
+        }
+    }
+}
+
+fn parse_input(input: &str) -> (Vec<Vec<String>>, Vec<Command>) {
+    let split = input.split("\n\n").collect::<Vec<&str>>();
+    (parse_stacks(split[0]), parse_commands(split[1]))
+}
+
+fn parse_stacks(drawing: &str) -> Vec<Vec<String>> {
+    let lines = drawing.split('\n').collect::<Vec<&str>>();
+    let (footer, rows) = lines.split_last().unwrap();
+    // Every stack sits above its label in the footer, so a crate belongs to the nearest label
+    let label_centers = find_labels(footer)
+        .into_iter()
+        .map(|(start, end)| start + end)
+        .collect::<Vec<usize>>();
+    let mut stacks = label_centers
+        .iter()
+        .map(|_| vec![])
+        .collect::<Vec<Vec<String>>>();
+    for row in rows.iter().rev() {
+        for (start, end) in find_crates(row) {
+            let center = start + end;
+            let stack = (0..label_centers.len())
+                .min_by_key(|&i| label_centers[i].abs_diff(center))
+                .expect("Drawing has no stack labels");
+            stacks[stack].push(row[start + 1..end].to_string());
+        }
+    }
+    stacks
+}
+
+// Start and end (inclusive) of every label in the footer line
+fn find_labels(footer: &str) -> Vec<(usize, usize)> {
+    let mut labels = vec![];
+    let mut start = None;
+    for (i, c) in footer.char_indices().chain([(footer.len(), ' ')]) {
+        match (c == ' ', start) {
+            (false, None) => start = Some(i),
+            (true, Some(s)) => {
+                labels.push((s, i - 1));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    labels
+}
 
+// Start and end (inclusive) of every bracketed crate in a row
+fn find_crates(row: &str) -> Vec<(usize, usize)> {
+    let mut crates = vec![];
+    let mut position = 0;
+    while let Some(start) = row[position..].find('[').map(|i| i + position) {
+        let end = start
+            + row[start..]
+                .find(']')
+                .unwrap_or_else(|| panic!("Unclosed crate in row: {}", row));
+        crates.push((start, end));
+        position = end + 1;
+    }
+    crates
+}
+
+fn parse_commands(input: &str) -> Vec<Command> {
     let re = Regex::new(r"move ([0-9]+) from ([0-9]+) to ([0-9]+)").unwrap();
-    let commands = split[1]
-        .to_string()
+    input
         .split('\n')
         .map(|line| {
             let caps = re.captures(line).unwrap();
@@ -59,56 +173,471 @@ fn parse_input(input: &str) -> (Vec<Vec<char>>, Vec<Command>) {
                 to: caps.get(3).unwrap().as_str().parse::<usize>().unwrap(),
             }
         })
-        .collect::<Vec<Command>>();
-    (
-        stacks
-            .into_iter()
-            .map(|s| s.into_iter().rev().collect::<Vec<char>>())
-            .collect::<Vec<Vec<char>>>(),
-        commands,
-    )
+        .collect::<Vec<Command>>()
+}
+
+// Draws the stacks the way the puzzle input does, with every row padded to the full width.
+// A column is as wide as its widest crate or label.
+fn render_stacks(stacks: &[Vec<String>]) -> String {
+    let widths = stacks
+        .iter()
+        .enumerate()
+        .map(|(i, s)| {
+            s.iter()
+                .map(|c| c.len() + 2)
+                .chain([(i + 1).to_string().len(), 3])
+                .max()
+                .unwrap()
+        })
+        .collect::<Vec<usize>>();
+    let height = stacks.iter().map(|s| s.len()).max().unwrap_or(0);
+    let mut lines = (0..height)
+        .rev()
+        .map(|level| {
+            stacks
+                .iter()
+                .zip(&widths)
+                .map(|(s, &width)| {
+                    let content = s.get(level).map_or(String::new(), |c| format!("[{}]", c));
+                    format!("{:^width$}", content, width = width)
+                })
+                .collect::<Vec<String>>()
+                .join(" ")
+        })
+        .collect::<Vec<String>>();
+    let footer = widths
+        .iter()
+        .enumerate()
+        .map(|(i, &width)| format!("{:^width$}", i + 1, width = width))
+        .collect::<Vec<String>>()
+        .join(" ");
+    lines.push(footer.trim_end().to_string());
+    lines.join("\n")
 }
 
-fn print_top_elements(stacks: &[Vec<char>]) -> String {
+fn print_top_elements(stacks: &[Vec<String>]) -> String {
     stacks
         .iter()
         .filter(|s| !s.is_empty())
-        .map(|s| s.last().unwrap())
+        .map(|s| s.last().unwrap().as_str())
         .collect::<String>()
 }
 
-fn part_1(input: &str) -> String {
-    let (mut stacks, commands) = parse_input(input);
-    for command in commands {
-        let Command { amount, from, to } = command;
+// A crane moves the crates of a command in one or more lifts
+trait Crane {
+    // Number of crates in each lift needed to move `amount` crates
+    fn lift_sizes(&self, amount: usize) -> Vec<usize> {
+        vec![amount]
+    }
 
-        for _ in 0..amount {
-            let e = stacks[from - 1].pop().unwrap();
-            stacks[to - 1].push(e);
-        }
+    // Whether a lifted block is put down upside down
+    fn reverses_block(&self) -> bool {
+        false
+    }
+
+    fn cost(&self, _block: &[String]) -> u32 {
+        1
     }
-    print_top_elements(&stacks)
 }
 
-fn part_2(input: &str) -> String {
-    let (mut stacks, commands) = parse_input(input);
-    for command in commands {
-        let Command { amount, from, to } = command;
-        let mut elements = vec![];
-        for _ in 0..amount {
-            elements.push(stacks[from - 1].pop().unwrap());
+struct CrateMover9000;
+
+impl Crane for CrateMover9000 {
+    fn lift_sizes(&self, amount: usize) -> Vec<usize> {
+        vec![1; amount]
+    }
+}
+
+struct CrateMover9001;
+
+impl Crane for CrateMover9001 {}
+
+// A single lift, with the crates in the order they end up on the target stack
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Move {
+    command: usize,
+    from: usize,
+    to: usize,
+    crates: Vec<String>,
+    cost: u32,
+}
+
+impl Move {
+    fn apply(&self, stacks: &mut [Vec<String>]) {
+        let from = &mut stacks[self.from - 1];
+        from.truncate(from.len() - self.crates.len());
+        stacks[self.to - 1].extend(self.crates.iter().cloned());
+    }
+}
+
+struct Simulator<C: Crane> {
+    crane: C,
+    stacks: Vec<Vec<String>>,
+    commands_executed: usize,
+    log: Vec<Move>,
+    print_drawings: bool,
+}
+
+impl<C: Crane> Simulator<C> {
+    fn new(crane: C, stacks: Vec<Vec<String>>) -> Simulator<C> {
+        Simulator {
+            crane,
+            stacks,
+            commands_executed: 0,
+            log: vec![],
+            print_drawings: false,
+        }
+    }
+
+    fn execute(&mut self, command: &Command) -> Result<(), MoveError> {
+        let heights = self.stacks.iter().map(Vec::len).collect::<Vec<usize>>();
+        command.validate(self.commands_executed, &heights)?;
+        let Command { amount, from, to } = *command;
+        let lifts = self.crane.lift_sizes(amount as usize);
+        let lifted = lifts.iter().sum();
+        if lifted != amount as usize {
+            return Err(MoveError {
+                command: self.commands_executed,
+                from,
+                to,
+                kind: MoveErrorKind::InvalidLifts {
+                    amount: amount as usize,
+                    lifted,
+                },
+            });
+        }
+        for lift in lifts {
+            let source = &self.stacks[from - 1];
+            let mut crates = source[source.len() - lift..].to_vec();
+            if self.crane.reverses_block() {
+                crates.reverse();
+            }
+            let step = Move {
+                command: self.commands_executed,
+                from,
+                to,
+                cost: self.crane.cost(&crates),
+                crates,
+            };
+            step.apply(&mut self.stacks);
+            self.log.push(step);
         }
-        for _ in 0..amount {
-            stacks[to - 1].push(elements.pop().unwrap())
+        self.commands_executed += 1;
+        if self.print_drawings {
+            println!("{}\n", render_stacks(&self.stacks));
         }
+        Ok(())
+    }
+
+    fn run(&mut self, commands: &[Command]) -> Result<(), MoveError> {
+        commands
+            .iter()
+            .try_for_each(|command| self.execute(command))
+    }
+
+    fn total_cost(&self) -> u32 {
+        self.log.iter().map(|step| step.cost).sum()
+    }
+}
+
+// Stack states after every move of the log, starting from the initial layout
+fn replay(initial: &[Vec<String>], log: &[Move]) -> Vec<Vec<Vec<String>>> {
+    let mut stacks = initial.to_vec();
+    log.iter()
+        .map(|step| {
+            step.apply(&mut stacks);
+            stacks.clone()
+        })
+        .collect()
+}
+
+// Every lift of the simulation with the layout after it, followed by the total cost
+fn print_replay<C: Crane>(initial: &[Vec<String>], simulator: Simulator<C>) {
+    for (step, stacks) in simulator.log.iter().zip(replay(initial, &simulator.log)) {
+        println!(
+            "Command {}: {} from {} to {} (cost {})",
+            step.command,
+            step.crates.concat(),
+            step.from,
+            step.to,
+            step.cost
+        );
+        println!("{}\n", render_stacks(&stacks));
+    }
+    println!("Total cost: {}", simulator.total_cost());
+}
+
+// Checks a whole command list against the initial layout without moving any crates
+fn dry_run(stacks: &[Vec<String>], commands: &[Command]) -> Result<(), MoveError> {
+    let mut heights = stacks.iter().map(Vec::len).collect::<Vec<usize>>();
+    for (index, command) in commands.iter().enumerate() {
+        command.validate(index, &heights)?;
+        heights[command.from - 1] -= command.amount as usize;
+        heights[command.to - 1] += command.amount as usize;
     }
-    print_top_elements(&stacks)
+    Ok(())
+}
+
+fn simulate<C: Crane>(
+    crane: C,
+    input: &str,
+    print_drawings: bool,
+) -> Result<Simulator<C>, MoveError> {
+    let (stacks, commands) = parse_input(input);
+    let mut simulator = Simulator::new(crane, stacks);
+    simulator.print_drawings = print_drawings;
+    if print_drawings {
+        println!("{}\n", render_stacks(&simulator.stacks));
+    }
+    simulator.run(&commands)?;
+    Ok(simulator)
+}
+
+fn part_1(input: &str) -> Result<String, MoveError> {
+    Ok(print_top_elements(
+        &simulate(CrateMover9000, input, false)?.stacks,
+    ))
+}
+
+fn part_2(input: &str) -> Result<String, MoveError> {
+    Ok(print_top_elements(
+        &simulate(CrateMover9001, input, false)?.stacks,
+    ))
+}
+
+// One crate per character, from bottom to top
+#[cfg(test)]
+fn to_stack(crates: &str) -> Vec<String> {
+    crates.chars().map(|c| c.to_string()).collect()
 }
 
 #[test]
 fn test_example() {
     let input = include_str!("../example.txt");
 
-    assert_eq!("CMZ", part_1(input).as_str());
-    assert_eq!("MCD", part_2(input).as_str());
+    assert_eq!("CMZ", part_1(input).unwrap().as_str());
+    assert_eq!("MCD", part_2(input).unwrap().as_str());
+}
+
+// Lifts at most `max` crates at once and pays for every crate
+#[cfg(test)]
+struct LimitedCrane {
+    max: usize,
+}
+
+#[cfg(test)]
+impl Crane for LimitedCrane {
+    fn lift_sizes(&self, amount: usize) -> Vec<usize> {
+        let mut sizes = vec![self.max; amount / self.max];
+        if !amount.is_multiple_of(self.max) {
+            sizes.push(amount % self.max);
+        }
+        sizes
+    }
+
+    fn cost(&self, block: &[String]) -> u32 {
+        block.len() as u32
+    }
+}
+
+#[cfg(test)]
+struct ReversingCrane;
+
+#[cfg(test)]
+impl Crane for ReversingCrane {
+    fn reverses_block(&self) -> bool {
+        true
+    }
+}
+
+// Claims to move more crates than asked for
+#[cfg(test)]
+struct OverreachingCrane;
+
+#[cfg(test)]
+impl Crane for OverreachingCrane {
+    fn lift_sizes(&self, amount: usize) -> Vec<usize> {
+        vec![amount, 1]
+    }
+}
+
+#[test]
+fn test_custom_cranes() {
+    let input = include_str!("../example.txt");
+
+    // Putting a block down reversed is the same as moving crates one at a time
+    assert_eq!(
+        "CMZ",
+        print_top_elements(&simulate(ReversingCrane, input, false).unwrap().stacks)
+    );
+    assert_eq!(4, simulate(ReversingCrane, input, false).unwrap().log.len());
+
+    let limited = simulate(LimitedCrane { max: 2 }, input, false).unwrap();
+    assert_eq!("MCZ", print_top_elements(&limited.stacks));
+    assert_eq!(5, limited.log.len());
+    assert_eq!(7, limited.total_cost());
+    assert_eq!(
+        7,
+        simulate(CrateMover9000, input, false).unwrap().total_cost()
+    );
+
+    let (stacks, commands) = parse_input(input);
+    let mut simulator = Simulator::new(OverreachingCrane, stacks.clone());
+    assert_eq!(
+        Err(MoveError {
+            command: 0,
+            from: 2,
+            to: 1,
+            kind: MoveErrorKind::InvalidLifts {
+                amount: 1,
+                lifted: 2
+            },
+        }),
+        simulator.run(&commands)
+    );
+    assert_eq!(stacks, simulator.stacks);
+}
+
+#[test]
+fn test_replay() {
+    let input = include_str!("../example.txt");
+    let (initial, _) = parse_input(input);
+    let simulator = simulate(CrateMover9001, input, false).unwrap();
+
+    assert_eq!(
+        Move {
+            command: 1,
+            from: 1,
+            to: 3,
+            crates: to_stack("ZND"),
+            cost: 1
+        },
+        simulator.log[1]
+    );
+    let states = replay(&initial, &simulator.log);
+    assert_eq!(
+        vec![to_stack("ZND"), to_stack("MC"), to_stack("P")],
+        states[0]
+    );
+    assert_eq!(&simulator.stacks, states.last().unwrap());
+}
+
+#[test]
+fn test_render_stacks() {
+    let stacks = vec![to_stack("ZN"), to_stack("MCD"), to_stack("P"), vec![]];
+    assert_eq!(
+        "    [D]        \n[N] [C]        \n[Z] [M] [P]    \n 1   2   3   4",
+        render_stacks(&stacks)
+    );
+    assert_eq!(" 1   2", render_stacks(&[vec![], vec![]]));
+
+    let drawing = include_str!("../input.txt").split("\n\n").next().unwrap();
+    assert_eq!(drawing, render_stacks(&parse_stacks(drawing)));
+}
+
+#[test]
+fn test_render_round_trip() {
+    // Every stack count, with ragged heights and crate labels of one to three letters
+    for stack_count in 1..=12 {
+        for shape in 0..24 {
+            let stacks = (0..stack_count)
+                .map(|stack| {
+                    (0..(shape + stack * 5) % 8)
+                        .map(|level| {
+                            (0..1 + (shape + stack + level) % 3)
+                                .map(|i| (b'A' + ((stack * 7 + level * 3 + i) % 26) as u8) as char)
+                                .collect()
+                        })
+                        .collect()
+                })
+                .collect::<Vec<Vec<String>>>();
+            let rendered = render_stacks(&stacks);
+            assert_eq!(stacks, parse_stacks(&rendered));
+            assert_eq!(rendered, render_stacks(&parse_stacks(&rendered)));
+        }
+    }
+
+    let drawing = include_str!("../example.txt").split("\n\n").next().unwrap();
+    let stacks = parse_stacks(drawing);
+    assert_eq!(stacks, parse_stacks(&render_stacks(&stacks)));
+}
+
+#[test]
+fn test_invalid_commands() {
+    let stacks = parse_stacks(include_str!("../example.txt").split("\n\n").next().unwrap());
+    let commands = parse_commands("move 1 from 2 to 1\nmove 4 from 1 to 3");
+    let expected = MoveError {
+        command: 1,
+        from: 1,
+        to: 3,
+        kind: MoveErrorKind::NotEnoughCrates {
+            requested: 4,
+            available: 3,
+        },
+    };
+    assert_eq!(Err(&expected), dry_run(&stacks, &commands).as_ref());
+
+    let mut simulator = Simulator::new(CrateMover9001, stacks.clone());
+    assert_eq!(Err(expected), simulator.run(&commands));
+    // The invalid command did not move anything
+    assert_eq!(
+        vec![to_stack("ZND"), to_stack("MC"), to_stack("P")],
+        simulator.stacks
+    );
+    assert_eq!(1, simulator.log.len());
+
+    let commands = parse_commands("move 1 from 2 to 4");
+    assert_eq!(
+        Err(MoveError {
+            command: 0,
+            from: 2,
+            to: 4,
+            kind: MoveErrorKind::UnknownStack {
+                stack: 4,
+                stack_count: 3,
+            },
+        }),
+        dry_run(&stacks, &commands)
+    );
+    assert_eq!(
+        "Command 0 (from 2 to 4): stack 4 does not exist, there are 3 stacks",
+        Simulator::new(CrateMover9000, stacks)
+            .run(&commands)
+            .unwrap_err()
+            .to_string()
+    );
+    assert_eq!(
+        Ok(()),
+        dry_run(&[to_stack("A")], &parse_commands("move 1 from 1 to 1"))
+    );
+}
+
+#[test]
+fn test_ragged_and_wide_drawings() {
+    // Trailing whitespace stripped, long crate labels and a second digit in the footer
+    let drawing = "[AB]      [D]\n[C]  [EF] [G]\n 1    2    3";
+    assert_eq!(
+        vec![
+            vec!["C".to_string(), "AB".to_string()],
+            vec!["EF".to_string()],
+            to_stack("GD")
+        ],
+        parse_stacks(drawing)
+    );
+
+    let stacks = (0..12)
+        .map(|i| to_stack(&"X".repeat(i % 3)))
+        .collect::<Vec<Vec<String>>>();
+    let rendered = render_stacks(&stacks);
+    assert_eq!(
+        [
+            "        [X]         [X]         [X]         [X]",
+            "    [X] [X]     [X] [X]     [X] [X]     [X] [X]",
+            " 1   2   3   4   5   6   7   8   9  10  11  12",
+        ]
+        .join("\n"),
+        rendered
+    );
+    assert_eq!(stacks, parse_stacks(&rendered));
+    assert_eq!("[X]\n 1", render_stacks(&parse_stacks("   [X]\n 1")));
 }
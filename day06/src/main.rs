@@ -1,47 +1,296 @@
 extern crate core;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::io::{self, Read};
 
 fn main() {
+    // With --stream, decode stdin instead of solving the puzzle input
+    if std::env::args().any(|arg| arg == "--stream") {
+        for event in Decoder::new(io::stdin().lock(), 4096) {
+            match event {
+                Ok(Event::Message { offset, data }) => {
+                    println!("Message at {}: {} bytes", offset, data.len())
+                }
+                Ok(event) => println!("{:?}", event),
+                Err(error) => println!("{}", error),
+            }
+        }
+        return;
+    }
     let input = include_str!("../input.txt");
-    println!("{}", part_1(input));
-    println!("{}", part_2(input));
+    for marker in [part_1(input), part_2(input)] {
+        match marker {
+            Some(position) => println!("{}", position),
+            None => println!("No marker found"),
+        }
+    }
 }
 
-fn part_1(input: &str) -> u32 {
-    input
-        .chars()
-        .collect::<Vec<char>>()
-        .windows(4)
-        .enumerate()
-        .find(|(_, slice)| {
-            let s: HashSet<char> = (**slice).iter().cloned().collect();
-            s.len() == 4
-        })
-        .unwrap()
-        .0 as u32
-        + 4
+// Keeps a running count of every element in the window, so each step is O(1)
+struct MarkerDetector<T> {
+    window: usize,
+    recent: VecDeque<T>,
+    counts: HashMap<T, usize>,
 }
 
-fn part_2(input: &str) -> u32 {
+impl<T: Copy + Eq + Hash> MarkerDetector<T> {
+    fn new(window: usize) -> MarkerDetector<T> {
+        MarkerDetector {
+            window,
+            recent: VecDeque::with_capacity(window + 1),
+            counts: HashMap::new(),
+        }
+    }
+
+    // Returns whether the last `window` elements are all different
+    fn push(&mut self, element: T) -> bool {
+        if self.window == 0 {
+            return false;
+        }
+        self.recent.push_back(element);
+        *self.counts.entry(element).or_insert(0) += 1;
+        if self.recent.len() > self.window {
+            let leaving = self.recent.pop_front().unwrap();
+            let count = self.counts.get_mut(&leaving).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                self.counts.remove(&leaving);
+            }
+        }
+        self.counts.len() == self.window
+    }
+
+    fn reset(&mut self) {
+        self.recent.clear();
+        self.counts.clear();
+    }
+}
+
+// Positions (number of characters read) after which the last `window` characters were all different.
+// They are found lazily, so taking the first one stops reading there.
+fn find_markers(input: &str, window: usize) -> impl Iterator<Item = usize> + '_ {
+    let mut detector = MarkerDetector::new(window);
     input
         .chars()
-        .collect::<Vec<char>>()
-        .windows(14)
         .enumerate()
-        .find(|(_, slice)| {
-            let s: HashSet<char> = (**slice).iter().cloned().collect();
-            s.len() == 14
-        })
-        .unwrap()
-        .0 as u32
-        + 14
+        .filter(move |&(_, c)| detector.push(c))
+        .map(|(i, _)| i + 1)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Event {
+    StartOfPacket { offset: usize },
+    StartOfMessage { offset: usize },
+    // Everything between a start-of-message marker and the next marker or the end of the stream
+    Message { offset: usize, data: Vec<u8> },
+}
+
+// Reads a datastream in chunks and emits events with absolute offsets as soon as they are found.
+// After each start-of-message marker the detector starts over, so the next marker must not overlap it.
+struct Decoder<R> {
+    reader: R,
+    chunk: Vec<u8>,
+    offset: usize,
+    packet_detector: MarkerDetector<u8>,
+    packet_found: bool,
+    message_detector: MarkerDetector<u8>,
+    message: Option<(usize, Vec<u8>)>,
+    pending: VecDeque<Event>,
+    done: bool,
+}
+
+impl<R: Read> Decoder<R> {
+    fn new(reader: R, chunk_size: usize) -> Decoder<R> {
+        Decoder {
+            reader,
+            chunk: vec![0; chunk_size.max(1)],
+            offset: 0,
+            packet_detector: MarkerDetector::new(4),
+            packet_found: false,
+            message_detector: MarkerDetector::new(14),
+            message: None,
+            pending: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    fn process(&mut self, byte: u8) {
+        self.offset += 1;
+        if !self.packet_found && self.packet_detector.push(byte) {
+            self.packet_found = true;
+            self.pending.push_back(Event::StartOfPacket {
+                offset: self.offset,
+            });
+        }
+        if self.message_detector.push(byte) {
+            self.message_detector.reset();
+            if let Some((offset, mut data)) = self.message.take() {
+                // The rest of the new marker is not part of the previous message
+                data.truncate(data.len() + 1 - self.message_detector.window);
+                self.pending.push_back(Event::Message { offset, data });
+            }
+            self.pending.push_back(Event::StartOfMessage {
+                offset: self.offset,
+            });
+            self.message = Some((self.offset, vec![]));
+        } else if let Some((_, data)) = &mut self.message {
+            data.push(byte);
+        }
+    }
+}
+
+impl<R: Read> Iterator for Decoder<R> {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<io::Result<Event>> {
+        while self.pending.is_empty() && !self.done {
+            match self.reader.read(&mut self.chunk) {
+                Ok(0) => {
+                    self.done = true;
+                    if let Some((offset, data)) = self.message.take() {
+                        self.pending.push_back(Event::Message { offset, data });
+                    }
+                }
+                Ok(read) => {
+                    for i in 0..read {
+                        let byte = self.chunk[i];
+                        self.process(byte);
+                    }
+                }
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => {
+                    self.done = true;
+                    return Some(Err(error));
+                }
+            }
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+fn first_marker(input: &str, window: usize) -> Option<usize> {
+    find_markers(input, window).next()
+}
+
+fn part_1(input: &str) -> Option<usize> {
+    first_marker(input, 4)
+}
+
+fn part_2(input: &str) -> Option<usize> {
+    first_marker(input, 14)
 }
 
 #[test]
 fn test_example() {
     let input = include_str!("../example.txt");
 
-    assert_eq!(7, part_1(input));
-    assert_eq!(19, part_2(input));
+    assert_eq!(Some(7), part_1(input));
+    assert_eq!(Some(19), part_2(input));
+}
+
+#[test]
+fn test_markers() {
+    assert_eq!(Some(5), part_1("bvwbjplbgvbhsrlpgdmjqwftvncz"));
+    assert_eq!(Some(23), part_2("bvwbjplbgvbhsrlpgdmjqwftvncz"));
+    let markers = |input, window| find_markers(input, window).collect::<Vec<usize>>();
+    assert_eq!(vec![4, 5, 6, 7], markers("abcdefa", 4));
+    assert_eq!(vec![2, 3, 5], markers("abcca", 2));
+    assert_eq!(Vec::<usize>::new(), markers("abcca", 0));
+    assert_eq!(None, part_1("aaaaaaaa"));
+    assert_eq!(None, part_2("abcd"));
+}
+
+// Hands out at most `max` bytes per read, to exercise chunk boundaries
+#[cfg(test)]
+struct TrickleReader<'a> {
+    data: &'a [u8],
+    max: usize,
+}
+
+#[cfg(test)]
+impl Read for TrickleReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.data.len().min(self.max).min(buf.len());
+        buf[..read].copy_from_slice(&self.data[..read]);
+        self.data = &self.data[read..];
+        Ok(read)
+    }
+}
+
+#[test]
+fn test_decoder() {
+    let input = include_str!("../input.txt");
+    let events = Decoder::new(input.as_bytes(), 64)
+        .collect::<io::Result<Vec<Event>>>()
+        .unwrap();
+    assert_eq!(
+        Event::StartOfPacket {
+            offset: part_1(input).unwrap()
+        },
+        events[0]
+    );
+    assert!(events.contains(&Event::StartOfMessage {
+        offset: part_2(input).unwrap()
+    }));
+
+    let events = Decoder::new("ababcdefghijklmnbaabcdefghijklmnab".as_bytes(), 3)
+        .collect::<io::Result<Vec<Event>>>()
+        .unwrap();
+    assert_eq!(
+        vec![
+            Event::StartOfPacket { offset: 6 },
+            Event::StartOfMessage { offset: 16 },
+            Event::Message {
+                offset: 16,
+                data: b"ba".to_vec()
+            },
+            Event::StartOfMessage { offset: 32 },
+            Event::Message {
+                offset: 32,
+                data: b"ab".to_vec()
+            },
+        ],
+        events
+    );
+}
+
+#[test]
+fn test_decoder_large_stream() {
+    // Payloads only use letters that the marker starts with, so the marker is found exactly at its end
+    let marker = b"abcdefghijklmn";
+    // Payload lengths around the chunk and read sizes, cycled until every one of them has been
+    // seen at several offsets into a chunk
+    let lengths = [
+        0, 1, 2, 13, 14, 15, 1008, 1009, 1010, 1999, 4095, 4096, 4097, 9000,
+    ];
+    let mut stream = vec![];
+    let mut expected = vec![];
+    for &length in lengths.iter().cycle() {
+        if stream.len() >= 50_000 {
+            break;
+        }
+        stream.extend_from_slice(marker);
+        let payload = b"abba"
+            .iter()
+            .copied()
+            .cycle()
+            .take(length)
+            .collect::<Vec<u8>>();
+        expected.push((stream.len(), payload.clone()));
+        stream.extend(payload);
+    }
+
+    let reader = TrickleReader {
+        data: &stream,
+        max: 1009,
+    };
+    let messages = Decoder::new(reader, 4096)
+        .map(Result::unwrap)
+        .filter_map(|event| match event {
+            Event::Message { offset, data } => Some((offset, data)),
+            _ => None,
+        })
+        .collect::<Vec<(usize, Vec<u8>)>>();
+    assert_eq!(expected, messages);
 }
@@ -1,18 +1,519 @@
-use regex::Regex;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::path::Path;
 
 fn main() {
     let input = include_str!("../input.txt");
-    println!("{}", part_1(input));
-    println!("{}", part_2(input));
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    if args.is_empty() {
+        println!("{}", part_1(input));
+        println!("{}", part_2(input, 70000000, 30000000).unwrap());
+        return;
+    }
+    // Queries on the file system of the puzzle input, e.g. `find '*.txt'` or `free 70000000 30000000`
+    let file_system = FileSystem::from_transcript(input).unwrap_or_else(|e| panic!("{}", e));
+    let number = |i: usize| -> u64 {
+        args.get(i)
+            .and_then(|arg| arg.parse().ok())
+            .unwrap_or_else(|| panic!("Argument {} must be a number", i))
+    };
+    match args[0].as_str() {
+        "tree" => print!("{}", file_system.tree()),
+        "du" => {
+            for (path, size) in file_system.du() {
+                println!("{}\t{}", size, path);
+            }
+        }
+        "find" => {
+            for path in file_system.find(&args[1]) {
+                println!("{}", path);
+            }
+        }
+        "largest" => {
+            for (path, size) in file_system.largest_files(number(1) as usize) {
+                println!("{}\t{}", size, path);
+            }
+        }
+        "free" => {
+            for (path, size) in file_system.deletion_candidates(number(1), number(2)) {
+                println!("{}\t{}", size, path);
+            }
+        }
+        // Generates a transcript for a local directory or a file with a tree description
+        "transcript" => {
+            let path = Path::new(&args[1]);
+            let file_system = if path.is_dir() {
+                FileSystem::from_directory(path).unwrap()
+            } else {
+                FileSystem::from_description(&std::fs::read_to_string(path).unwrap())
+                    .unwrap_or_else(|e| panic!("{}", e))
+            };
+            let options = match args.get(2) {
+                Some(_) => TranscriptOptions {
+                    seed: number(2),
+                    shuffle: true,
+                    repeat_ls: true,
+                    jump_to_root: true,
+                },
+                None => TranscriptOptions::default(),
+            };
+            println!("{}", file_system.transcript(&options));
+        }
+        query => println!("Unknown query: {}", query),
+    }
+}
+
+#[derive(Debug)]
+enum NodeKind {
+    Directory { children: BTreeMap<String, usize> },
+    File { size: u64 },
+}
+
+#[derive(Debug)]
+struct Node {
+    name: String,
+    parent: Option<usize>,
+    kind: NodeKind,
+}
+
+// Nodes are stored in the order they are discovered, so every parent comes before its children.
+// The root directory is always node 0.
+#[derive(Debug)]
+struct FileSystem {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum TranscriptError {
+    InvalidLine { line: usize, content: String },
+    NotADirectory { line: usize, name: String },
+    NotAFile { line: usize, name: String },
+}
+
+impl fmt::Display for TranscriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TranscriptError::InvalidLine { line, content } => {
+                write!(f, "Line {}: cannot parse {:?}", line, content)
+            }
+            TranscriptError::NotADirectory { line, name } => {
+                write!(f, "Line {}: {} is a file, not a directory", line, name)
+            }
+            TranscriptError::NotAFile { line, name } => {
+                write!(f, "Line {}: {} is a directory, not a file", line, name)
+            }
+        }
+    }
+}
+
+impl FileSystem {
+    fn new() -> FileSystem {
+        FileSystem {
+            nodes: vec![Node {
+                name: String::from("/"),
+                parent: None,
+                kind: NodeKind::Directory {
+                    children: BTreeMap::new(),
+                },
+            }],
+        }
+    }
+
+    fn from_transcript(input: &str) -> Result<FileSystem, TranscriptError> {
+        let mut file_system = FileSystem::new();
+        let mut current = 0;
+        for (index, line) in input.split('\n').enumerate() {
+            let line_number = index + 1;
+            let invalid = || TranscriptError::InvalidLine {
+                line: line_number,
+                content: line.to_string(),
+            };
+            if let Some(target) = line.strip_prefix("$ cd ") {
+                current = match target {
+                    "/" => 0,
+                    ".." => file_system.nodes[current].parent.unwrap_or(0),
+                    name => file_system.add_directory(current, name).ok_or_else(|| {
+                        TranscriptError::NotADirectory {
+                            line: line_number,
+                            name: name.to_string(),
+                        }
+                    })?,
+                };
+            } else if line == "$ ls" || line.is_empty() {
+                // Listings are merged by name, so listing a directory twice is harmless
+            } else if let Some(name) = line.strip_prefix("dir ") {
+                file_system.add_directory(current, name).ok_or_else(|| {
+                    TranscriptError::NotADirectory {
+                        line: line_number,
+                        name: name.to_string(),
+                    }
+                })?;
+            } else {
+                let (size, name) = line.split_once(' ').ok_or_else(invalid)?;
+                let size = size.parse::<u64>().map_err(|_| invalid())?;
+                file_system.add_file(current, name, size).ok_or_else(|| {
+                    TranscriptError::NotAFile {
+                        line: line_number,
+                        name: name.to_string(),
+                    }
+                })?;
+            }
+        }
+        Ok(file_system)
+    }
+
+    fn children(&self, node: usize) -> Option<&BTreeMap<String, usize>> {
+        match &self.nodes[node].kind {
+            NodeKind::Directory { children } => Some(children),
+            NodeKind::File { .. } => None,
+        }
+    }
+
+    fn add_node(&mut self, parent: usize, name: &str, kind: NodeKind) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            name: name.to_string(),
+            parent: Some(parent),
+            kind,
+        });
+        if let NodeKind::Directory { children } = &mut self.nodes[parent].kind {
+            children.insert(name.to_string(), index);
+        }
+        index
+    }
+
+    // Returns the existing or new directory, or None if a file has that name
+    fn add_directory(&mut self, parent: usize, name: &str) -> Option<usize> {
+        match self.children(parent)?.get(name) {
+            Some(&existing) => self.children(existing).map(|_| existing),
+            None => Some(self.add_node(
+                parent,
+                name,
+                NodeKind::Directory {
+                    children: BTreeMap::new(),
+                },
+            )),
+        }
+    }
+
+    // Returns the existing or new file, or None if a directory has that name
+    fn add_file(&mut self, parent: usize, name: &str, size: u64) -> Option<usize> {
+        match self.children(parent)?.get(name) {
+            Some(&existing) => match &mut self.nodes[existing].kind {
+                NodeKind::File { size: old_size } => {
+                    *old_size = size;
+                    Some(existing)
+                }
+                NodeKind::Directory { .. } => None,
+            },
+            None => Some(self.add_node(parent, name, NodeKind::File { size })),
+        }
+    }
+
+    fn path(&self, node: usize) -> String {
+        match self.nodes[node].parent {
+            None => String::from("/"),
+            Some(0) => format!("/{}", self.nodes[node].name),
+            Some(parent) => format!("{}/{}", self.path(parent), self.nodes[node].name),
+        }
+    }
+
+    // Total size of every node, files included
+    fn sizes(&self) -> Vec<u64> {
+        let mut sizes = self
+            .nodes
+            .iter()
+            .map(|node| match node.kind {
+                NodeKind::File { size } => size,
+                NodeKind::Directory { .. } => 0,
+            })
+            .collect::<Vec<u64>>();
+        for (index, node) in self.nodes.iter().enumerate().rev() {
+            if let Some(parent) = node.parent {
+                sizes[parent] += sizes[index];
+            }
+        }
+        sizes
+    }
+
+    fn file_size(&self, node: usize) -> Option<u64> {
+        match self.nodes[node].kind {
+            NodeKind::File { size } => Some(size),
+            NodeKind::Directory { .. } => None,
+        }
+    }
+
+    // Listing in the format of the puzzle description
+    fn tree(&self) -> String {
+        let sizes = self.sizes();
+        let mut lines = String::new();
+        let mut stack = vec![(0, 0)];
+        while let Some((node, depth)) = stack.pop() {
+            let kind = match self.children(node) {
+                Some(children) => {
+                    stack.extend(children.values().rev().map(|&child| (child, depth + 1)));
+                    "dir"
+                }
+                None => "file",
+            };
+            lines += &format!(
+                "{}- {} ({}, size={})\n",
+                "  ".repeat(depth),
+                self.nodes[node].name,
+                kind,
+                sizes[node]
+            );
+        }
+        lines
+    }
+
+    // All directories with their total size, largest first
+    fn du(&self) -> Vec<(String, u64)> {
+        let sizes = self.sizes();
+        let mut directories = (0..self.nodes.len())
+            .filter(|&node| self.children(node).is_some())
+            .map(|node| (self.path(node), sizes[node]))
+            .collect::<Vec<(String, u64)>>();
+        directories.sort_by(|(p1, s1), (p2, s2)| s2.cmp(s1).then(p1.cmp(p2)));
+        directories
+    }
+
+    // Paths of all files matching the glob. Patterns containing a slash match the full path,
+    // all others only the file name.
+    fn find(&self, pattern: &str) -> Vec<String> {
+        let mut paths = (0..self.nodes.len())
+            .filter(|&node| self.file_size(node).is_some())
+            .map(|node| self.path(node))
+            .filter(|path| {
+                let name = path.rsplit('/').next().unwrap();
+                glob_matches(pattern, if pattern.contains('/') { path } else { name })
+            })
+            .collect::<Vec<String>>();
+        paths.sort();
+        paths
+    }
+
+    fn largest_files(&self, count: usize) -> Vec<(String, u64)> {
+        let mut files = (0..self.nodes.len())
+            .filter_map(|node| Some((self.path(node), self.file_size(node)?)))
+            .collect::<Vec<(String, u64)>>();
+        files.sort_by(|(p1, s1), (p2, s2)| s2.cmp(s1).then(p1.cmp(p2)));
+        files.truncate(count);
+        files
+    }
+
+    // Directories that free enough space when deleted, smallest first.
+    // Empty if there already is enough free space.
+    fn deletion_candidates(&self, total_space: u64, needed_space: u64) -> Vec<(String, u64)> {
+        let used_space = self.sizes()[0];
+        let space_to_delete = needed_space.saturating_sub(total_space.saturating_sub(used_space));
+        if space_to_delete == 0 {
+            return vec![];
+        }
+        let mut candidates = self
+            .du()
+            .into_iter()
+            .filter(|(_, size)| *size >= space_to_delete)
+            .collect::<Vec<(String, u64)>>();
+        candidates.reverse();
+        candidates
+    }
 }
 
-// https://stackoverflow.com/questions/68837763/how-to-iterate-prefixes-or-suffixes-of-vec-or-slice-in-rust
-pub fn prefixes_asc<T>(slice: &[T]) -> impl Iterator<Item = &[T]> + DoubleEndedIterator {
-    (0..=slice.len()).map(move |len| &slice[..len])
+// Supports `*` for any number of characters and `?` for a single character
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let text = text.chars().collect::<Vec<char>>();
+    let (mut p, mut t) = (0, 0);
+    // Position after the last `*` and the text position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            p += 1;
+            backtrack = Some((p, t));
+        } else if let Some((star_p, star_t)) = backtrack {
+            p = star_p;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
 }
 
-fn part_1(input: &str) -> u32 {
+// Small xorshift generator, so transcripts can be shuffled without extra dependencies
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+
+    fn chance(&mut self) -> bool {
+        self.next(2) == 0
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.next(i + 1));
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct TranscriptOptions {
+    seed: u64,
+    // Visit directories in random order and list their contents in random order
+    shuffle: bool,
+    // Sometimes run `ls` twice in the same directory
+    repeat_ls: bool,
+    // Sometimes navigate with `cd /` instead of `cd ..`
+    jump_to_root: bool,
+}
+
+impl FileSystem {
+    fn from_directory(path: &Path) -> io::Result<FileSystem> {
+        let mut file_system = FileSystem::new();
+        let mut pending = vec![(0, path.to_path_buf())];
+        while let Some((node, directory)) = pending.pop() {
+            for entry in std::fs::read_dir(&directory)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                // Symbolic links are not followed
+                let metadata = entry.path().symlink_metadata()?;
+                if metadata.is_dir() {
+                    let child = file_system.add_directory(node, &name).unwrap();
+                    pending.push((child, entry.path()));
+                } else if metadata.is_file() {
+                    file_system.add_file(node, &name, metadata.len()).unwrap();
+                }
+            }
+        }
+        Ok(file_system)
+    }
+
+    // Every line is either `dir <path>` or `<size> <path>`, with absolute paths
+    fn from_description(description: &str) -> Result<FileSystem, TranscriptError> {
+        let mut file_system = FileSystem::new();
+        for (index, line) in description.lines().enumerate() {
+            let line_number = index + 1;
+            let invalid = || TranscriptError::InvalidLine {
+                line: line_number,
+                content: line.to_string(),
+            };
+            let (kind, path) = line.split_once(' ').ok_or_else(invalid)?;
+            let mut names = path
+                .strip_prefix('/')
+                .ok_or_else(invalid)?
+                .split('/')
+                .collect::<Vec<&str>>();
+            let name = names.pop().unwrap();
+            let mut parent = 0;
+            for directory in names {
+                parent = file_system
+                    .add_directory(parent, directory)
+                    .ok_or_else(|| TranscriptError::NotADirectory {
+                        line: line_number,
+                        name: directory.to_string(),
+                    })?;
+            }
+            if kind == "dir" {
+                file_system.add_directory(parent, name).ok_or_else(|| {
+                    TranscriptError::NotADirectory {
+                        line: line_number,
+                        name: name.to_string(),
+                    }
+                })?;
+            } else {
+                let size = kind.parse::<u64>().map_err(|_| invalid())?;
+                file_system.add_file(parent, name, size).ok_or_else(|| {
+                    TranscriptError::NotAFile {
+                        line: line_number,
+                        name: name.to_string(),
+                    }
+                })?;
+            }
+        }
+        Ok(file_system)
+    }
+
+    fn ancestors(&self, node: usize) -> Vec<usize> {
+        let mut ancestors = vec![node];
+        while let Some(parent) = self.nodes[*ancestors.last().unwrap()].parent {
+            ancestors.push(parent);
+        }
+        ancestors.reverse();
+        ancestors
+    }
+
+    // A terminal session that lists every directory, so parsing it gives back this file system
+    fn transcript(&self, options: &TranscriptOptions) -> String {
+        let mut rng = XorShift(options.seed.max(1));
+        let mut directories = (0..self.nodes.len())
+            .filter(|&node| self.children(node).is_some())
+            .collect::<Vec<usize>>();
+        if options.shuffle {
+            rng.shuffle(&mut directories);
+        } else {
+            // Depth first, like walking the tree by hand
+            directories.sort_by_key(|&node| {
+                self.ancestors(node)
+                    .iter()
+                    .map(|&n| self.nodes[n].name.clone())
+                    .collect::<Vec<String>>()
+            });
+        }
+        let mut lines = vec![String::from("$ cd /")];
+        let mut current = vec![0];
+        for directory in directories {
+            let target = self.ancestors(directory);
+            if options.jump_to_root && current.len() > 1 && rng.chance() {
+                lines.push(String::from("$ cd /"));
+                current.truncate(1);
+            }
+            while !target.starts_with(&current) {
+                lines.push(String::from("$ cd .."));
+                current.pop();
+            }
+            for &node in &target[current.len()..] {
+                lines.push(format!("$ cd {}", self.nodes[node].name));
+            }
+            current = target;
+            let listings = if options.repeat_ls && rng.chance() {
+                2
+            } else {
+                1
+            };
+            for _ in 0..listings {
+                let mut children = self
+                    .children(directory)
+                    .unwrap()
+                    .values()
+                    .copied()
+                    .collect::<Vec<usize>>();
+                if options.shuffle {
+                    rng.shuffle(&mut children);
+                }
+                lines.push(String::from("$ ls"));
+                lines.extend(children.iter().map(|&child| match self.file_size(child) {
+                    Some(size) => format!("{} {}", size, self.nodes[child].name),
+                    None => format!("dir {}", self.nodes[child].name),
+                }));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+fn part_1(input: &str) -> u64 {
     get_directory_sizes(input)
         .iter()
         .filter(|(_, sum)| **sum < 100000)
@@ -20,43 +521,23 @@ fn part_1(input: &str) -> u32 {
         .sum()
 }
 
-fn part_2(input: &str) -> u32 {
-    let total_space = 70000000;
-    let needed_space = 30000000;
-    let directory_sizes = get_directory_sizes(input);
-    let available_space = total_space - *directory_sizes.get("/").unwrap();
-    let space_to_delete = needed_space - available_space;
-    let mut possible_directory_sizes: Vec<u32> = directory_sizes
-        .iter()
-        .filter(|(_, s)| **s > space_to_delete)
-        .map(|(_, s)| *s)
-        .collect();
-    possible_directory_sizes.sort_unstable();
-    possible_directory_sizes[0]
-}
-
-fn get_directory_sizes(input: &str) -> HashMap<String, u32> {
-    let mut directory_sizes = HashMap::new();
-    let mut directory_path: Vec<&str> = vec![];
-    let commands = input.split('\n').collect::<Vec<&str>>();
-    let cd_regex = Regex::new(r"\$ cd ([a-z]+|/)").unwrap();
-    for c in commands {
-        if cd_regex.is_match(c) {
-            let caps = cd_regex.captures(c).unwrap();
-            directory_path.push(caps.get(1).unwrap().as_str());
-        } else if c == "$ cd .." {
-            directory_path.pop();
-        } else if c.starts_with("dir") || c.starts_with("$ ls") {
-            // Ignore, assuming that ls is only done once per directory
-        } else {
-            let size = c.split(' ').next().unwrap().parse::<u32>().unwrap();
-            for prefix in prefixes_asc(directory_path.as_slice()) {
-                let full_directory = prefix.join("/");
-                *directory_sizes.entry(full_directory).or_insert(0) += size;
-            }
-        }
-    }
-    directory_sizes
+// Size of the smallest directory to delete, None if there already is enough free space
+fn part_2(input: &str, total_space: u64, needed_space: u64) -> Option<u64> {
+    let file_system = FileSystem::from_transcript(input).unwrap_or_else(|e| panic!("{}", e));
+    file_system
+        .deletion_candidates(total_space, needed_space)
+        .first()
+        .map(|(_, size)| *size)
+}
+
+// Sizes of all directories by their full path
+fn get_directory_sizes(input: &str) -> HashMap<String, u64> {
+    let file_system = FileSystem::from_transcript(input).unwrap_or_else(|e| panic!("{}", e));
+    let sizes = file_system.sizes();
+    (0..file_system.nodes.len())
+        .filter(|&node| file_system.children(node).is_some())
+        .map(|node| (file_system.path(node), sizes[node]))
+        .collect()
 }
 
 #[test]
@@ -64,5 +545,182 @@ fn test_example() {
     let input = include_str!("../example.txt");
 
     assert_eq!(95437, part_1(input));
-    assert_eq!(24933642, part_2(input));
+    assert_eq!(Some(24933642), part_2(input, 70000000, 30000000));
+}
+
+#[test]
+fn test_file_system() {
+    let input = "$ cd /\n$ ls\ndir a.b\n10 x\n$ cd a.b\n$ ls\n20 file 1.txt\ndir 2nd\n\
+                 $ ls\n20 file 1.txt\n$ cd 2nd\n$ ls\n5000000000 big.img\n$ cd /\n$ cd ..\n$ ls\n10 x";
+    let sizes = get_directory_sizes(input);
+
+    assert_eq!(3, sizes.len());
+    assert_eq!(Some(&5000000030), sizes.get("/"));
+    assert_eq!(Some(&5000000020), sizes.get("/a.b"));
+    assert_eq!(Some(&5000000000), sizes.get("/a.b/2nd"));
+
+    assert_eq!(
+        Err(TranscriptError::NotADirectory {
+            line: 3,
+            name: String::from("x")
+        }),
+        FileSystem::from_transcript("$ ls\n10 x\n$ cd x").map(|_| ())
+    );
+    assert_eq!(
+        Err(TranscriptError::InvalidLine {
+            line: 2,
+            content: String::from("ten x")
+        }),
+        FileSystem::from_transcript("$ ls\nten x").map(|_| ())
+    );
+}
+
+#[test]
+fn test_queries() {
+    let file_system = FileSystem::from_transcript(include_str!("../example.txt")).unwrap();
+
+    assert_eq!(
+        [
+            "- / (dir, size=48381165)",
+            "  - a (dir, size=94853)",
+            "    - e (dir, size=584)",
+            "      - i (file, size=584)",
+            "    - f (file, size=29116)",
+            "    - g (file, size=2557)",
+            "    - h.lst (file, size=62596)",
+            "  - b.txt (file, size=14848514)",
+            "  - c.dat (file, size=8504156)",
+            "  - d (dir, size=24933642)",
+            "    - d.ext (file, size=5626152)",
+            "    - d.log (file, size=8033020)",
+            "    - j (file, size=4060174)",
+            "    - k (file, size=7214296)",
+            "",
+        ]
+        .join("\n"),
+        file_system.tree()
+    );
+    assert_eq!(
+        vec![
+            (String::from("/"), 48381165),
+            (String::from("/d"), 24933642),
+            (String::from("/a"), 94853),
+            (String::from("/a/e"), 584)
+        ],
+        file_system.du()
+    );
+    assert_eq!(vec!["/d/d.ext", "/d/d.log"], file_system.find("d.*"));
+    assert_eq!(vec!["/a/e/i"], file_system.find("/a/*/?"));
+    assert_eq!(vec!["/b.txt"], file_system.find("*.t?t"));
+    assert_eq!(
+        vec![
+            (String::from("/b.txt"), 14848514),
+            (String::from("/c.dat"), 8504156)
+        ],
+        file_system.largest_files(2)
+    );
+    assert_eq!(
+        vec![
+            (String::from("/a"), 94853),
+            (String::from("/d"), 24933642),
+            (String::from("/"), 48381165)
+        ],
+        file_system.deletion_candidates(48381165, 60000)
+    );
+    assert!(file_system.deletion_candidates(70000000, 1000).is_empty());
+    assert_eq!(None, part_2(include_str!("../example.txt"), 70000000, 1000));
+}
+
+#[test]
+fn test_glob_matches() {
+    assert!(glob_matches("*", ""));
+    assert!(glob_matches("a*b*c", "aXbYbc"));
+    assert!(!glob_matches("a*b", "aXbc"));
+    assert!(glob_matches("?.txt", "a.txt"));
+    assert!(!glob_matches("?.txt", "ab.txt"));
+}
+
+#[test]
+fn test_transcript_round_trip() {
+    let input = include_str!("../example.txt");
+    let file_system = FileSystem::from_transcript(input).unwrap();
+    let transcript = file_system.transcript(&TranscriptOptions::default());
+    assert!(transcript
+        .starts_with("$ cd /\n$ ls\ndir a\n14848514 b.txt\n8504156 c.dat\ndir d\n$ cd a\n"));
+    assert_eq!(
+        file_system.tree(),
+        FileSystem::from_transcript(&transcript).unwrap().tree()
+    );
+
+    let description = "dir /empty\n10 /a b/c.d\n20 /a b/e/f\n5000000000 /big.img\n30 /a b/e/g h";
+    let file_system = FileSystem::from_description(description).unwrap();
+    for seed in 1..200 {
+        let options = TranscriptOptions {
+            seed,
+            shuffle: true,
+            repeat_ls: true,
+            jump_to_root: true,
+        };
+        let transcript = file_system.transcript(&options);
+        assert_eq!(
+            file_system.du(),
+            FileSystem::from_transcript(&transcript).unwrap().du()
+        );
+        assert_eq!(
+            file_system.tree(),
+            FileSystem::from_transcript(&transcript).unwrap().tree()
+        );
+    }
+
+    // A name cannot be both a file and a directory, whichever comes first
+    assert_eq!(
+        Some(TranscriptError::NotADirectory {
+            line: 2,
+            name: String::from("x"),
+        }),
+        FileSystem::from_description("10 /a/x\ndir /a/x").err()
+    );
+    assert_eq!(
+        Some(TranscriptError::NotAFile {
+            line: 2,
+            name: String::from("x"),
+        }),
+        FileSystem::from_description("dir /a/x\n10 /a/x").err()
+    );
+    assert_eq!(
+        Some(TranscriptError::NotADirectory {
+            line: 2,
+            name: String::from("x"),
+        }),
+        FileSystem::from_description("10 /a/x\n20 /a/x/y").err()
+    );
+}
+
+#[test]
+fn test_transcript_from_directory() {
+    let root = std::env::temp_dir().join(format!("day07-transcript-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("sub dir/nested")).unwrap();
+    std::fs::create_dir_all(root.join("empty")).unwrap();
+    std::fs::write(root.join("a.txt"), "hello").unwrap();
+    std::fs::write(root.join("sub dir/b"), vec![0; 1234]).unwrap();
+    std::fs::write(root.join("sub dir/nested/c.bin"), vec![0; 4321]).unwrap();
+
+    let file_system = FileSystem::from_directory(&root).unwrap();
+    std::fs::remove_dir_all(&root).unwrap();
+    let options = TranscriptOptions {
+        seed: 7,
+        shuffle: true,
+        repeat_ls: true,
+        jump_to_root: true,
+    };
+    let parsed = FileSystem::from_transcript(&file_system.transcript(&options)).unwrap();
+    assert_eq!(
+        vec![
+            (String::from("/"), 5560),
+            (String::from("/sub dir"), 5555),
+            (String::from("/sub dir/nested"), 4321),
+            (String::from("/empty"), 0)
+        ],
+        parsed.du()
+    );
 }
@@ -2,54 +2,107 @@ use array2d::Array2D;
 
 fn main() {
     let input = include_str!("../input.txt");
-    println!("{}", part_1(input));
-    println!("{}", part_2(input));
+    match std::env::args().nth(1).as_deref() {
+        None => {
+            println!("{}", part_1(input));
+            println!("{}", part_2(input));
+        }
+        Some(query) => {
+            let analysis = ForestAnalysis::new(&parse_input_to_matrix(input));
+            match query {
+                "sites" => {
+                    let k = std::env::args().nth(2).map_or(10, |k| k.parse().unwrap());
+                    for site in analysis.best_sites(k) {
+                        let distance = |d: &Array2D<usize>| *d.get(site.row, site.col).unwrap();
+                        println!(
+                            "({}, {}): {} (left {}, right {}, up {}, down {})",
+                            site.row,
+                            site.col,
+                            site.score,
+                            distance(&analysis.distances.left),
+                            distance(&analysis.distances.right),
+                            distance(&analysis.distances.up),
+                            distance(&analysis.distances.down)
+                        );
+                    }
+                }
+                "visible" => println!("{}", analysis.visible_count()),
+                "heatmap" => println!("{}", analysis.heat_map_text()),
+                "pgm" => print!("{}", analysis.heat_map_pgm()),
+                _ => println!("Unknown query: {}", query),
+            }
+        }
+    }
 }
 
 fn part_1(input: &str) -> u32 {
     let tree_matrix = parse_input_to_matrix(input);
-    let dim = tree_matrix.column_len();
-    let mut visible_matrix = Array2D::filled_with(false, dim, dim);
+    calculate_visibility(&tree_matrix)
+        .elements_row_major_iter()
+        .filter(|visibility| visibility.is_visible())
+        .count() as u32
+}
 
-    for (y, row) in tree_matrix.rows_iter().enumerate() {
+// Edges of the forest from which a tree can be seen
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Visibility {
+    left: bool,
+    right: bool,
+    top: bool,
+    bottom: bool,
+}
+
+impl Visibility {
+    fn is_visible(&self) -> bool {
+        self.left || self.right || self.top || self.bottom
+    }
+}
+
+fn calculate_visibility(tree_matrix: &Array2D<i32>) -> Array2D<Visibility> {
+    let rows = tree_matrix.num_rows();
+    let columns = tree_matrix.num_columns();
+    let mut visible_matrix = Array2D::filled_with(Visibility::default(), rows, columns);
+
+    for y in 0..rows {
         let mut current_max = -1;
-        for (x, tree) in row.enumerate() {
+        for x in 0..columns {
+            let tree = tree_matrix.get(y, x).unwrap();
             if *tree > current_max {
                 current_max = *tree;
-                visible_matrix.set(y, x, true).unwrap();
+                visible_matrix.get_mut(y, x).unwrap().left = true;
             }
         }
         // right to left
-
         current_max = -1;
-        for x in (0..dim).rev() {
+        for x in (0..columns).rev() {
             let tree = tree_matrix.get(y, x).unwrap();
             if *tree > current_max {
                 current_max = *tree;
-                visible_matrix.set(y, x, true).unwrap();
+                visible_matrix.get_mut(y, x).unwrap().right = true;
             }
         }
     }
 
-    for (x, col) in tree_matrix.columns_iter().enumerate() {
+    for x in 0..columns {
         let mut current_max = -1;
-        for (y, tree) in col.enumerate() {
+        for y in 0..rows {
+            let tree = tree_matrix.get(y, x).unwrap();
             if *tree > current_max {
                 current_max = *tree;
-                visible_matrix.set(y, x, true).unwrap();
+                visible_matrix.get_mut(y, x).unwrap().top = true;
             }
         }
         // down to up
         current_max = -1;
-        for y in (0..dim).rev() {
+        for y in (0..rows).rev() {
             let tree = tree_matrix.get(y, x).unwrap();
             if *tree > current_max {
                 current_max = *tree;
-                visible_matrix.set(y, x, true).unwrap();
+                visible_matrix.get_mut(y, x).unwrap().bottom = true;
             }
         }
     }
-    visible_matrix.as_row_major().iter().filter(|b| **b).count() as u32
+    visible_matrix
 }
 
 fn parse_input_to_matrix(input: &str) -> Array2D<i32> {
@@ -65,13 +118,16 @@ fn parse_input_to_matrix(input: &str) -> Array2D<i32> {
     )
 }
 
-fn calculate_scenic_score(matrix: &Array2D<i32>, row: usize, col: usize) -> i32 {
-    let dim = matrix.column_len();
-    if row == 0 || col == 0 || row == dim - 1 || col == dim - 1 {
+// Rescans from a single tree, the stack-based scores are checked against it
+#[cfg(test)]
+fn calculate_scenic_score(matrix: &Array2D<i32>, row: usize, col: usize) -> usize {
+    let rows = matrix.num_rows();
+    let columns = matrix.num_columns();
+    if row == 0 || col == 0 || row == rows - 1 || col == columns - 1 {
         return 0;
     }
 
-    let score_accumulator = |(count, stop), element| -> (i32, bool) {
+    let score_accumulator = |(count, stop), element| -> (usize, bool) {
         match stop {
             true => (count, stop),
             false => (count + 1, element >= matrix.get(row, col).unwrap()),
@@ -85,14 +141,14 @@ fn calculate_scenic_score(matrix: &Array2D<i32>, row: usize, col: usize) -> i32
     let mut col_vec_rev = col_vec[0..row].to_vec();
     col_vec_rev.reverse();
 
-    vec![
+    [
         row_vec_rev.iter().fold((0, false), score_accumulator).0,
-        row_vec[col + 1..dim]
+        row_vec[col + 1..columns]
             .iter()
             .fold((0, false), score_accumulator)
             .0,
         col_vec_rev.iter().fold((0, false), score_accumulator).0,
-        col_vec[(row + 1)..dim]
+        col_vec[(row + 1)..rows]
             .iter()
             .fold((0, false), score_accumulator)
             .0,
@@ -101,16 +157,188 @@ fn calculate_scenic_score(matrix: &Array2D<i32>, row: usize, col: usize) -> i32
     .product()
 }
 
-fn part_2(input: &str) -> i32 {
-    let tree_matrix = parse_input_to_matrix(input);
-    let dim = tree_matrix.column_len();
-    let mut best_view = i32::MIN;
-    for y in 0..dim - 1 {
-        for x in 0..dim - 1 {
-            best_view = i32::max(best_view, calculate_scenic_score(&tree_matrix, y, x))
+// Number of trees visible from every tree when looking in each direction
+struct ViewingDistances {
+    left: Array2D<usize>,
+    right: Array2D<usize>,
+    up: Array2D<usize>,
+    down: Array2D<usize>,
+}
+
+// Viewing distance from every tree towards the start of the line. The stack keeps the trees
+// that can still block the view, from the last one that is at least as tall as the current tree.
+fn distances_towards_start(line: &[i32]) -> Vec<usize> {
+    let mut stack: Vec<usize> = vec![];
+    line.iter()
+        .enumerate()
+        .map(|(i, height)| {
+            while stack.last().is_some_and(|&j| line[j] < *height) {
+                stack.pop();
+            }
+            let distance = stack.last().map_or(i, |&j| i - j);
+            stack.push(i);
+            distance
+        })
+        .collect()
+}
+
+fn calculate_viewing_distances(matrix: &Array2D<i32>) -> ViewingDistances {
+    let towards_end = |line: Vec<i32>| {
+        let mut reversed = line;
+        reversed.reverse();
+        let mut distances = distances_towards_start(&reversed);
+        distances.reverse();
+        distances
+    };
+    let rows = matrix.as_rows();
+    let columns = matrix.as_columns();
+    let from_rows = |distances: Vec<Vec<usize>>| Array2D::from_rows(&distances);
+    let from_columns = |distances: Vec<Vec<usize>>| Array2D::from_columns(&distances);
+    ViewingDistances {
+        left: from_rows(
+            rows.iter()
+                .map(|row| distances_towards_start(row))
+                .collect(),
+        ),
+        right: from_rows(rows.iter().map(|row| towards_end(row.clone())).collect()),
+        up: from_columns(
+            columns
+                .iter()
+                .map(|col| distances_towards_start(col))
+                .collect(),
+        ),
+        down: from_columns(columns.iter().map(|col| towards_end(col.clone())).collect()),
+    }
+}
+
+fn calculate_scenic_scores(matrix: &Array2D<i32>) -> Array2D<usize> {
+    scenic_scores(&calculate_viewing_distances(matrix))
+}
+
+fn scenic_scores(distances: &ViewingDistances) -> Array2D<usize> {
+    let (rows, columns) = (distances.left.num_rows(), distances.left.num_columns());
+    let mut scores = Array2D::filled_with(0, rows, columns);
+    for row in 0..rows {
+        for col in 0..columns {
+            let score = [
+                &distances.left,
+                &distances.right,
+                &distances.up,
+                &distances.down,
+            ]
+            .iter()
+            .map(|d| d.get(row, col).unwrap())
+            .product();
+            scores.set(row, col, score).unwrap();
         }
     }
-    best_view
+    scores
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Site {
+    row: usize,
+    col: usize,
+    score: usize,
+}
+
+// Everything there is to know about the forest when picking a treehouse site
+struct ForestAnalysis {
+    visibility: Array2D<Visibility>,
+    distances: ViewingDistances,
+    scores: Array2D<usize>,
+}
+
+impl ForestAnalysis {
+    fn new(matrix: &Array2D<i32>) -> ForestAnalysis {
+        let distances = calculate_viewing_distances(matrix);
+        ForestAnalysis {
+            visibility: calculate_visibility(matrix),
+            scores: scenic_scores(&distances),
+            distances,
+        }
+    }
+
+    fn visible_count(&self) -> usize {
+        self.visibility
+            .elements_row_major_iter()
+            .filter(|visibility| visibility.is_visible())
+            .count()
+    }
+
+    // The `k` trees with the highest scenic score, best first and in reading order on ties
+    fn best_sites(&self, k: usize) -> Vec<Site> {
+        let mut sites = (0..self.scores.num_rows())
+            .flat_map(|row| (0..self.scores.num_columns()).map(move |col| (row, col)))
+            .map(|(row, col)| Site {
+                row,
+                col,
+                score: *self.scores.get(row, col).unwrap(),
+            })
+            .collect::<Vec<Site>>();
+        sites.sort_by_key(|site| (std::cmp::Reverse(site.score), site.row, site.col));
+        sites.truncate(k);
+        sites
+    }
+
+    // Scores scaled to `levels` steps between zero and the best score. The square root keeps
+    // ordinary trees from vanishing next to a few outstanding sites.
+    fn scaled_scores(&self, levels: usize) -> Vec<Vec<usize>> {
+        let best = self
+            .scores
+            .elements_row_major_iter()
+            .copied()
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let scale = (best as f64).sqrt();
+        self.scores
+            .as_rows()
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|&score| ((score as f64).sqrt() / scale * (levels - 1) as f64) as usize)
+                    .collect()
+            })
+            .collect()
+    }
+
+    // One character per tree, from ' ' for no view at all to '@' for the best site
+    fn heat_map_text(&self) -> String {
+        let shades = " .:-=+*#%@".chars().collect::<Vec<char>>();
+        self.scaled_scores(shades.len())
+            .iter()
+            .map(|row| row.iter().map(|&level| shades[level]).collect::<String>())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    // Plain grayscale PGM image with one pixel per tree
+    fn heat_map_pgm(&self) -> String {
+        let mut image = format!(
+            "P2\n{} {}\n255\n",
+            self.scores.num_columns(),
+            self.scores.num_rows()
+        );
+        for row in self.scaled_scores(256) {
+            let pixels = row
+                .iter()
+                .map(|level| level.to_string())
+                .collect::<Vec<String>>();
+            image += &pixels.join(" ");
+            image += "\n";
+        }
+        image
+    }
+}
+
+fn part_2(input: &str) -> usize {
+    let tree_matrix = parse_input_to_matrix(input);
+    calculate_scenic_scores(&tree_matrix)
+        .elements_row_major_iter()
+        .copied()
+        .max()
+        .unwrap_or(0)
 }
 
 #[test]
@@ -121,6 +349,78 @@ fn test_example() {
     assert_eq!(8, part_2(input));
 }
 
+#[test]
+fn test_rectangular_forest() {
+    // The best tree sits in the last column but one, which the old loops skipped
+    let input = "1111111\n1111191\n1111111";
+    assert_eq!(17, part_1(input));
+    assert_eq!(5, part_2(input));
+    assert_eq!(16, part_1("12\n34\n56\n78\n12\n34\n56\n78"));
+
+    for (rows, columns) in [(1, 1), (1, 9), (9, 1), (5, 17), (23, 4), (30, 30)] {
+        // Heights that rise and fall unevenly along every row and column
+        let matrix = Array2D::from_iter_row_major(
+            (0..rows * columns).map(|i| ((i / columns * 7 + i % columns * 3 + i * i) % 10) as i32),
+            rows,
+            columns,
+        );
+        let scores = calculate_scenic_scores(&matrix);
+        for row in 0..rows {
+            for col in 0..columns {
+                assert_eq!(
+                    calculate_scenic_score(&matrix, row, col),
+                    *scores.get(row, col).unwrap()
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_forest_analysis() {
+    let matrix = parse_input_to_matrix(include_str!("../example.txt"));
+    let analysis = ForestAnalysis::new(&matrix);
+
+    assert_eq!(21, analysis.visible_count());
+    // The 5 in the middle of the second row is visible from the left and from the top
+    assert_eq!(
+        Visibility {
+            left: true,
+            right: false,
+            top: true,
+            bottom: false
+        },
+        *analysis.visibility.get(1, 1).unwrap()
+    );
+    assert!(!analysis.visibility.get(2, 2).unwrap().is_visible());
+    assert_eq!(
+        vec![
+            Site {
+                row: 3,
+                col: 2,
+                score: 8
+            },
+            Site {
+                row: 2,
+                col: 1,
+                score: 6
+            }
+        ],
+        analysis.best_sites(2)
+    );
+    assert_eq!(2, *analysis.distances.left.get(3, 2).unwrap());
+    assert_eq!(2, *analysis.distances.right.get(3, 2).unwrap());
+    assert_eq!(2, *analysis.distances.up.get(3, 2).unwrap());
+    assert_eq!(1, *analysis.distances.down.get(3, 2).unwrap());
+    assert_eq!(
+        "     \n -*- \n #-= \n -@+ \n     ",
+        analysis.heat_map_text()
+    );
+    assert!(analysis
+        .heat_map_pgm()
+        .starts_with("P2\n5 5\n255\n0 0 0 0 0\n0 90 180 90 0\n"));
+}
+
 /*
 fn print_array(array: &Array2D<bool>) {
     for row in array.rows_iter() {
@@ -1,14 +1,49 @@
 use regex::Regex;
 use std::collections::HashSet;
+use std::fmt;
 
 fn main() {
     let input = include_str!("../input.txt");
-    println!("{}", part_1(input));
-    println!("{}", part_2(input));
+    // With --3d, the motions are read as moves of a rope in three dimensions
+    if std::env::args().any(|arg| arg == "--3d") {
+        match (
+            count_tail_positions::<3>(input, 2),
+            count_tail_positions::<3>(input, 10),
+        ) {
+            (Ok(part_1), Ok(part_2)) => println!("{}\n{}", part_1, part_2),
+            (Err(error), _) | (_, Err(error)) => println!("{}", error),
+        }
+        return;
+    }
+    match (part_1(input), part_2(input)) {
+        (Ok(part_1), Ok(part_2)) => println!("{}\n{}", part_1, part_2),
+        (Err(error), _) | (_, Err(error)) => println!("{}", error),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum RopeError {
+    InvalidLine { line: usize, content: String },
+    UnknownCommand { line: usize, command: String },
+    NoKnots,
+}
+
+impl fmt::Display for RopeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RopeError::InvalidLine { line, content } => {
+                write!(f, "Line {}: cannot parse {:?}", line, content)
+            }
+            RopeError::UnknownCommand { line, command } => {
+                write!(f, "Line {}: unknown command {:?}", line, command)
+            }
+            RopeError::NoKnots => write!(f, "A rope needs at least one knot"),
+        }
+    }
 }
 
-fn is_touching(pos1: (i32, i32), pos2: (i32, i32)) -> bool {
-    i32::abs(pos1.0 - pos2.0) < 2 && i32::abs(pos1.1 - pos2.1) < 2
+fn is_touching(pos1: &[i32], pos2: &[i32]) -> bool {
+    pos1.iter().zip(pos2).all(|(a, b)| i32::abs(a - b) < 2)
 }
 
 fn clamp(val: i32, min: i32, max: i32) -> i32 {
@@ -21,72 +56,226 @@ fn clamp(val: i32, min: i32, max: i32) -> i32 {
     }
 }
 
-fn get_new_tail_position(head: (i32, i32), tail: (i32, i32)) -> (i32, i32) {
-    if head != tail && !is_touching(head, tail) {
-        return (
-            tail.0 + clamp(head.0 - tail.0, -1, 1),
-            tail.1 + clamp(head.1 - tail.1, -1, 1),
-        );
+// The tail moves one step towards the head along every axis, diagonally if needed
+fn get_new_tail_position<const D: usize>(head: [i32; D], tail: [i32; D]) -> [i32; D] {
+    if head != tail && !is_touching(&head, &tail) {
+        let mut new_tail = tail;
+        for axis in 0..D {
+            new_tail[axis] += clamp(head[axis] - tail[axis], -1, 1);
+        }
+        return new_tail;
     }
     tail
 }
 
-fn get_new_head_position(head: (i32, i32), command: &str) -> (i32, i32) {
-    match command {
-        "R" => (head.0 + 1, head.1),
-        "L" => (head.0 - 1, head.1),
-        "U" => (head.0, head.1 + 1),
-        "D" => (head.0, head.1 - 1),
-        _ => head,
+// Every letter moves the head along one axis: R/L along x, U/D along y and F/B along z.
+// Letters can be combined for diagonal moves, like UR or DLF.
+fn get_head_direction<const D: usize>(command: &str) -> Option<[i32; D]> {
+    let mut direction = [0; D];
+    for letter in command.chars() {
+        let (axis, delta) = match letter {
+            'R' => (0, 1),
+            'L' => (0, -1),
+            'U' => (1, 1),
+            'D' => (1, -1),
+            'F' => (2, 1),
+            'B' => (2, -1),
+            _ => return None,
+        };
+        if axis >= D || direction[axis] != 0 {
+            return None;
+        }
+        direction[axis] = delta;
+    }
+    if command.is_empty() {
+        return None;
+    }
+    Some(direction)
+}
+
+fn get_new_head_position<const D: usize>(head: [i32; D], direction: [i32; D]) -> [i32; D] {
+    let mut new_head = head;
+    for axis in 0..D {
+        new_head[axis] += direction[axis];
     }
+    new_head
 }
 
-fn part_1(input: &str) -> usize {
-    let mut positions = HashSet::new();
-    let r = Regex::new(r"([RULD]) ([0-9]+)").unwrap();
-    let mut h_pos = (0, 0);
-    let mut t_pos = (0, 0);
-    for line in input.split('\n') {
-        let caps = r.captures(line).unwrap();
-        let command = caps.get(1).unwrap().as_str();
-        let steps = caps.get(2).unwrap().as_str().parse::<i32>().unwrap();
-        for _ in 0..steps {
-            h_pos = get_new_head_position(h_pos, command);
-            t_pos = get_new_tail_position(h_pos, t_pos);
-            positions.insert(t_pos);
+fn parse_motions<const D: usize>(input: &str) -> Result<Vec<([i32; D], usize)>, RopeError> {
+    let r = Regex::new(r"^([A-Z]+) ([0-9]+)$").unwrap();
+    input
+        .split('\n')
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(index, line)| {
+            let invalid = || RopeError::InvalidLine {
+                line: index + 1,
+                content: line.to_string(),
+            };
+            let caps = r.captures(line).ok_or_else(invalid)?;
+            let command = caps.get(1).unwrap().as_str();
+            let steps = caps
+                .get(2)
+                .unwrap()
+                .as_str()
+                .parse::<usize>()
+                .map_err(|_| invalid())?;
+            let direction =
+                get_head_direction(command).ok_or_else(|| RopeError::UnknownCommand {
+                    line: index + 1,
+                    command: command.to_string(),
+                })?;
+            Ok((direction, steps))
+        })
+        .collect()
+}
+
+// Knot 0 is the head, every other knot follows the one before it
+struct Rope<const D: usize> {
+    knots: Vec<[i32; D]>,
+}
+
+impl<const D: usize> Rope<D> {
+    fn new(knot_count: usize) -> Result<Rope<D>, RopeError> {
+        if knot_count == 0 {
+            return Err(RopeError::NoKnots);
         }
+        Ok(Rope {
+            knots: vec![[0; D]; knot_count],
+        })
     }
 
-    positions.len()
-}
-
-fn part_2(input: &str) -> usize {
-    let mut positions = HashSet::new();
-    let r = Regex::new(r"([RULD]) ([0-9]+)").unwrap();
-    let mut h_pos = (0, 0);
-    let mut tail_knots = vec![];
-    tail_knots.resize(9, (0, 0));
-    for line in input.split('\n') {
-        let caps = r.captures(line).unwrap();
-        let command = caps.get(1).unwrap().as_str();
-        let steps = caps.get(2).unwrap().as_str().parse::<i32>().unwrap();
-        for _ in 0..steps {
-            h_pos = get_new_head_position(h_pos, command);
-            tail_knots[0] = get_new_tail_position(h_pos, tail_knots[0]);
-            for i in 0..8 {
-                tail_knots[i + 1] = get_new_tail_position(tail_knots[i], tail_knots[i + 1]);
+    fn step(&mut self, direction: [i32; D]) {
+        self.knots[0] = get_new_head_position(self.knots[0], direction);
+        for i in 1..self.knots.len() {
+            self.knots[i] = get_new_tail_position(self.knots[i - 1], self.knots[i]);
+        }
+    }
+
+    // `visit` sees the positions of all knots at the start and after every single step,
+    // so callers only keep as much of the history as they need
+    fn run(&mut self, motions: &[([i32; D], usize)], mut visit: impl FnMut(&[[i32; D]])) {
+        visit(&self.knots);
+        for (direction, steps) in motions {
+            for _ in 0..*steps {
+                self.step(*direction);
+                visit(&self.knots);
             }
-            positions.insert(tail_knots[8]);
         }
     }
+}
 
-    positions.len()
+fn count_tail_positions<const D: usize>(
+    input: &str,
+    knot_count: usize,
+) -> Result<usize, RopeError> {
+    let mut visited = HashSet::new();
+    Rope::<D>::new(knot_count)?.run(&parse_motions(input)?, |knots| {
+        visited.insert(knots[knot_count - 1]);
+    });
+    Ok(visited.len())
+}
+
+fn part_1(input: &str) -> Result<usize, RopeError> {
+    count_tail_positions::<2>(input, 2)
+}
+
+fn part_2(input: &str) -> Result<usize, RopeError> {
+    count_tail_positions::<2>(input, 10)
+}
+
+// Positions of all knots at the start and after every single step
+#[cfg(test)]
+fn knot_history<const D: usize>(knot_count: usize, input: &str) -> Vec<Vec<[i32; D]>> {
+    let mut history = vec![];
+    Rope::<D>::new(knot_count)
+        .unwrap()
+        .run(&parse_motions(input).unwrap(), |knots| {
+            history.push(knots.to_vec())
+        });
+    history
 }
 
 #[test]
 fn test_example() {
     let input = include_str!("../example.txt");
 
-    assert_eq!(13, part_1(input));
-    assert_eq!(1, part_2(input));
+    assert_eq!(Ok(13), part_1(input));
+    assert_eq!(Ok(1), part_2(input));
+}
+
+#[test]
+fn test_rope() {
+    let input = include_str!("../example.txt");
+    let history = knot_history::<2>(10, input);
+    let visited = |knot| {
+        history
+            .iter()
+            .map(|knots| knots[knot])
+            .collect::<HashSet<[i32; 2]>>()
+            .len()
+    };
+
+    assert_eq!(25, history.len());
+    assert_eq!(vec![[0, 0]; 10], history[0]);
+    // After R 4 the first knots lie behind the head in a line
+    assert_eq!(
+        vec![[4, 0], [3, 0], [2, 0], [1, 0]],
+        history[4][..4].to_vec()
+    );
+    assert_eq!(1, visited(9));
+    assert_eq!(21, visited(0));
+    assert_eq!(13, visited(1));
+
+    let larger_example = "R 5\nU 8\nL 8\nD 3\nR 17\nD 10\nL 25\nU 20";
+    assert_eq!(Ok(36), part_2(larger_example));
+    assert_eq!(Ok(88), count_tail_positions::<2>(larger_example, 2));
+    assert_eq!(Ok(visited(0)), count_tail_positions::<2>(input, 1));
+
+    // The starting cell counts as visited, even when nothing moves
+    assert_eq!(Ok(1), part_1(""));
+    assert_eq!(Ok(1), part_2("R 0\n"));
+    assert_eq!(Err(RopeError::NoKnots), count_tail_positions::<2>(input, 0));
+}
+
+#[test]
+fn test_diagonal_and_3d_moves() {
+    let history = knot_history::<2>(3, "UR 3\nDL 1");
+    assert_eq!(vec![[3, 3], [2, 2], [1, 1]], history[3]);
+    assert_eq!(vec![[2, 2], [2, 2], [1, 1]], history[4]);
+
+    let history = knot_history::<3>(2, "F 2\nUR 1\nB 3");
+    assert_eq!(vec![[0, 0, 2], [0, 0, 1]], history[2]);
+    assert_eq!(vec![[1, 1, 2], [0, 0, 1]], history[3]);
+    assert_eq!(vec![[1, 1, -1], [1, 1, 0]], history[6]);
+    assert_eq!(Ok(3), count_tail_positions::<3>("UFR 3", 2));
+
+    assert_eq!(
+        Err(RopeError::UnknownCommand {
+            line: 2,
+            command: String::from("F")
+        }),
+        part_1("R 1\nF 1")
+    );
+    assert_eq!(
+        Err(RopeError::UnknownCommand {
+            line: 1,
+            command: String::from("UD")
+        }),
+        count_tail_positions::<3>("UD 1", 2)
+    );
+    assert_eq!(
+        Err(RopeError::InvalidLine {
+            line: 1,
+            content: String::from("X")
+        }),
+        part_1("X")
+    );
+    assert_eq!(
+        Err(RopeError::InvalidLine {
+            line: 2,
+            content: String::from("R 99999999999999999999")
+        }),
+        part_1("U 1\nR 99999999999999999999")
+    );
 }
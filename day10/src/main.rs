@@ -1,68 +1,991 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, BufRead};
+use std::iter::Peekable;
+
 fn main() {
     let input = include_str!("../input.txt");
-    println!("{}", part_1(input));
-    println!("{}", part_2(input));
-}
-
-fn part_1(input: &str) -> i32 {
-    let mut x = 1;
-    let mut cycle_values = vec![];
-    for command in input.split('\n') {
-        cycle_values.push(x);
-        if command == "noop" {
-            continue;
-        }
-        let val = command.split_once(' ').unwrap().1.parse::<i32>().unwrap();
-        cycle_values.push(x);
-        x += val;
-    }
-    cycle_values[20 - 1] * 20
-        + cycle_values[60 - 1] * 60
-        + cycle_values[100 - 1] * 100
-        + cycle_values[140 - 1] * 140
-        + cycle_values[180 - 1] * 180
-        + cycle_values[220 - 1] * 220
-}
-
-fn part_2(input: &str) -> String {
-    let mut cycle = 0;
-    let mut sprite_index = 1;
-    let mut crt = vec![];
-    #[allow(clippy::explicit_counter_loop)]
-    for command in input.split('\n') {
-        crt.push(if i32::abs(sprite_index - (cycle % 40)) < 2 {
-            '#'
-        } else {
-            '.'
-        });
-        cycle += 1;
-        if command == "noop" {
-            continue;
-        }
-        let val = command.split_once(' ').unwrap().1.parse::<i32>().unwrap();
-
-        crt.push(if i32::abs(sprite_index - (cycle % 40)) < 2 {
-            '#'
-        } else {
-            '.'
-        });
-        cycle += 1;
-        sprite_index += val;
-    }
-    crt.chunks(40)
-        .map(|chunk| {
-            chunk
-                .iter()
-                .map(|c| c.to_string())
-                .collect::<Vec<String>>()
-                .join("")
+    // Cycles given as arguments are probed instead of solving the puzzle,
+    // or become breakpoints along with --debug
+    let mut debugging = false;
+    let mut cycles = vec![];
+    for arg in std::env::args().skip(1) {
+        match (arg.as_str(), arg.parse::<usize>()) {
+            ("--debug", _) => debugging = true,
+            (_, Ok(cycle)) => cycles.push(cycle),
+            (_, Err(_)) => {
+                return println!(
+                    "Unknown argument {:?}, expected --debug or cycle numbers",
+                    arg
+                )
+            }
+        }
+    }
+    if debugging {
+        debug(input, &cycles);
+        return;
+    }
+    if !cycles.is_empty() {
+        match measure_signal(input, &Probes::Cycles(cycles)) {
+            Ok(report) => {
+                for probe in report.probes {
+                    println!(
+                        "Cycle {}: X = {}, signal strength {}",
+                        probe.cycle, probe.x, probe.signal_strength
+                    );
+                }
+                println!("Sum: {}", report.sum);
+            }
+            Err(error) => println!("{}", error),
+        }
+        return;
+    }
+    match (part_1(input), part_2(input)) {
+        (Ok(part_1), Ok(part_2)) => {
+            println!("{}", part_1);
+            match read_letters(&part_2) {
+                Ok(letters) => println!("{}", letters),
+                Err(error) => println!("{}", error),
+            }
+            println!("{}", part_2);
+        }
+        (Err(error), _) | (_, Err(error)) => println!("{}", error),
+    }
+}
+
+// Reads debugger commands from stdin, so scripts can be piped in as well
+fn debug(input: &str, breakpoints: &[usize]) {
+    let instruction_set = InstructionSet::default();
+    let program = match instruction_set.decode(input) {
+        Ok(program) => program,
+        Err(error) => return println!("{}", error),
+    };
+    let mut debugger = Debugger::new(&instruction_set, program);
+    for cycle in breakpoints {
+        println!("{}", debugger.run_script(&format!("break {}", cycle)));
+    }
+    for line in io::stdin().lock().lines() {
+        let line = line.expect("Could not read command");
+        if line.trim() == "quit" {
+            break;
+        }
+        println!("{}", debugger.run_script(&line));
+    }
+}
+
+type Registers = BTreeMap<char, i32>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Instruction {
+    Noop,
+    // `addx V`, `addy V` and so on, for any single-letter register
+    Add(char, i32),
+    // An opcode registered in the instruction set
+    Custom(String, Vec<i32>),
+}
+
+// Opcodes beyond `noop` and `add`, plugged into an instruction set
+trait Opcode {
+    fn cycles(&self) -> usize;
+    fn execute(&self, registers: &mut Registers, args: &[i32]);
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum CpuError {
+    UnknownInstruction { line: usize, text: String },
+    ProgramTooShort { probe: usize, cycles: usize },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::UnknownInstruction { line, text } => {
+                write!(f, "Line {}: unknown instruction {:?}", line, text)
+            }
+            CpuError::ProgramTooShort { probe, cycles } => write!(
+                f,
+                "Cannot probe cycle {}, the program ends after {} cycles",
+                probe, cycles
+            ),
+        }
+    }
+}
+
+#[derive(Default)]
+struct InstructionSet {
+    opcodes: HashMap<String, Box<dyn Opcode>>,
+}
+
+impl InstructionSet {
+    // The puzzle itself only needs the built-in instructions
+    #[allow(dead_code)]
+    fn register(&mut self, name: &str, opcode: Box<dyn Opcode>) {
+        self.opcodes.insert(name.to_string(), opcode);
+    }
+
+    fn decode(&self, program: &str) -> Result<Vec<Instruction>, CpuError> {
+        program
+            .split('\n')
+            .enumerate()
+            .map(|(index, line)| {
+                let unknown = || CpuError::UnknownInstruction {
+                    line: index + 1,
+                    text: line.to_string(),
+                };
+                let mut parts = line.split(' ');
+                let name = parts.next().unwrap();
+                let args = parts
+                    .map(|arg| arg.parse::<i32>().map_err(|_| unknown()))
+                    .collect::<Result<Vec<i32>, CpuError>>()?;
+                if self.opcodes.contains_key(name) {
+                    return Ok(Instruction::Custom(name.to_string(), args));
+                }
+                match (name, args.as_slice()) {
+                    ("noop", []) => Ok(Instruction::Noop),
+                    (name, [value]) if name.len() == 4 && name.starts_with("add") => {
+                        Ok(Instruction::Add(name.chars().last().unwrap(), *value))
+                    }
+                    _ => Err(unknown()),
+                }
+            })
+            .collect()
+    }
+
+    fn cycles(&self, instruction: &Instruction) -> usize {
+        match instruction {
+            Instruction::Noop => 1,
+            Instruction::Add(_, _) => 2,
+            Instruction::Custom(name, _) => self.opcodes[name].cycles(),
+        }
+    }
+
+    fn execute(&self, instruction: &Instruction, registers: &mut Registers) {
+        match instruction {
+            Instruction::Noop => {}
+            Instruction::Add(register, value) => {
+                *registers.entry(*register).or_insert(0) += value;
+            }
+            Instruction::Custom(name, args) => self.opcodes[name].execute(registers, args),
+        }
+    }
+}
+
+// The registers during a cycle, before the running instruction has finished
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CycleState {
+    cycle: usize,
+    registers: Registers,
+    instruction: usize,
+}
+
+impl CycleState {
+    fn x(&self) -> i32 {
+        self.registers[&'x']
+    }
+}
+
+// Steps through the program one clock cycle at a time
+struct Cpu<'a> {
+    instruction_set: &'a InstructionSet,
+    program: Vec<Instruction>,
+    clock: usize,
+    registers: Registers,
+    pc: usize,
+    remaining_cycles: usize,
+}
+
+impl<'a> Cpu<'a> {
+    fn new(instruction_set: &'a InstructionSet, program: Vec<Instruction>) -> Cpu<'a> {
+        let remaining_cycles = program
+            .first()
+            .map_or(0, |instruction| instruction_set.cycles(instruction));
+        Cpu {
+            instruction_set,
+            program,
+            clock: 0,
+            registers: Registers::from([('x', 1)]),
+            pc: 0,
+            remaining_cycles,
+        }
+    }
+}
+
+impl Iterator for Cpu<'_> {
+    type Item = CycleState;
+
+    fn next(&mut self) -> Option<CycleState> {
+        // Instructions that take no cycles finish right away
+        while self.remaining_cycles == 0 {
+            let instruction = self.program.get(self.pc)?;
+            self.instruction_set
+                .execute(instruction, &mut self.registers);
+            self.pc += 1;
+            self.remaining_cycles = self
+                .program
+                .get(self.pc)
+                .map_or(0, |next| self.instruction_set.cycles(next));
+        }
+        self.clock += 1;
+        self.remaining_cycles -= 1;
+        let state = CycleState {
+            cycle: self.clock,
+            registers: self.registers.clone(),
+            instruction: self.pc,
+        };
+        if self.remaining_cycles == 0 {
+            self.instruction_set
+                .execute(&self.program[self.pc], &mut self.registers);
+            self.pc += 1;
+            self.remaining_cycles = self
+                .program
+                .get(self.pc)
+                .map_or(0, |next| self.instruction_set.cycles(next));
+        }
+        Some(state)
+    }
+}
+
+fn run_program(input: &str) -> Result<Vec<CycleState>, CpuError> {
+    let instruction_set = InstructionSet::default();
+    let program = instruction_set.decode(input)?;
+    Ok(Cpu::new(&instruction_set, program).collect())
+}
+
+// Cycles at which the signal strength is measured
+enum Probes {
+    Cycles(Vec<usize>),
+    Every {
+        start: usize,
+        step: usize,
+        count: usize,
+    },
+}
+
+impl Probes {
+    fn cycles(&self) -> Vec<usize> {
+        match self {
+            Probes::Cycles(cycles) => cycles.clone(),
+            Probes::Every { start, step, count } => (0..*count).map(|i| start + i * step).collect(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Probe {
+    cycle: usize,
+    x: i32,
+    signal_strength: i32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct SignalReport {
+    probes: Vec<Probe>,
+    sum: i32,
+}
+
+fn measure_signal(input: &str, probes: &Probes) -> Result<SignalReport, CpuError> {
+    let states = run_program(input)?;
+    let probes = probes
+        .cycles()
+        .into_iter()
+        .map(|cycle| {
+            let state = cycle
+                .checked_sub(1)
+                .and_then(|index| states.get(index))
+                .ok_or(CpuError::ProgramTooShort {
+                    probe: cycle,
+                    cycles: states.len(),
+                })?;
+            Ok(Probe {
+                cycle,
+                x: state.x(),
+                signal_strength: cycle as i32 * state.x(),
+            })
         })
+        .collect::<Result<Vec<Probe>, CpuError>>()?;
+    Ok(SignalReport {
+        sum: probes.iter().map(|probe| probe.signal_strength).sum(),
+        probes,
+    })
+}
+
+fn part_1(input: &str) -> Result<i32, CpuError> {
+    let probes = Probes::Every {
+        start: 20,
+        step: 40,
+        count: 6,
+    };
+    Ok(measure_signal(input, &probes)?.sum)
+}
+
+// The pixel the CRT draws during a cycle
+fn pixel(state: &CycleState) -> char {
+    let column = ((state.cycle - 1) % 40) as i32;
+    if i32::abs(state.x() - column) < 2 {
+        '#'
+    } else {
+        '.'
+    }
+}
+
+fn part_2(input: &str) -> Result<String, CpuError> {
+    let crt = run_program(input)?.iter().map(pixel).collect::<Vec<char>>();
+    Ok(crt
+        .chunks(40)
+        .map(|chunk| chunk.iter().collect::<String>())
         .collect::<Vec<String>>()
-        .join("\n")
+        .join("\n"))
+}
+
+// The capital letters of the 4x6 CRT font, with one blank column between letters.
+// I and Y are left out because their glyphs are not four columns wide, so they break the
+// fixed letter spacing, and D, M, N, Q, T, V, W and X have no glyph in this font.
+const FONT: [(char, [&str; 6]); 16] = [
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"]),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+enum OcrError {
+    InvalidImage {
+        rows: usize,
+    },
+    UnknownGlyph {
+        index: usize,
+        column: usize,
+        glyph: String,
+    },
+}
+
+impl fmt::Display for OcrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OcrError::InvalidImage { rows } => {
+                write!(f, "The CRT image has {} rows instead of 6", rows)
+            }
+            OcrError::UnknownGlyph {
+                index,
+                column,
+                glyph,
+            } => write!(
+                f,
+                "Unknown glyph {} at column {}:\n{}",
+                index, column, glyph
+            ),
+        }
+    }
+}
+
+// Turns the CRT image into the letters it shows
+fn read_letters(image: &str) -> Result<String, OcrError> {
+    let rows = image.split('\n').collect::<Vec<&str>>();
+    if rows.len() != 6 {
+        return Err(OcrError::InvalidImage { rows: rows.len() });
+    }
+    let width = rows.iter().map(|row| row.len()).max().unwrap();
+    (0..width)
+        .step_by(5)
+        .enumerate()
+        .map(|(index, column)| {
+            let glyph = rows
+                .iter()
+                .map(|row| row.get(column..(column + 4).min(row.len())).unwrap_or(""))
+                .collect::<Vec<&str>>();
+            FONT.iter()
+                .find(|(_, pattern)| pattern[..] == glyph[..])
+                .map(|(letter, _)| *letter)
+                .ok_or_else(|| OcrError::UnknownGlyph {
+                    index,
+                    column,
+                    glyph: glyph.join("\n"),
+                })
+        })
+        .collect()
+}
+
+// Conditions that stop the debugger when continuing
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Breakpoint {
+    Cycle(usize),
+    Register(char, i32),
+}
+
+impl Breakpoint {
+    fn parse(text: &str) -> Option<Breakpoint> {
+        match text.split_once('=') {
+            Some((register, value)) => {
+                let mut chars = register.trim().chars();
+                match (chars.next(), chars.next()) {
+                    (Some(register), None) if register.is_ascii_lowercase() => {
+                        Some(Breakpoint::Register(register, value.trim().parse().ok()?))
+                    }
+                    _ => None,
+                }
+            }
+            None => text.trim().parse().ok().map(Breakpoint::Cycle),
+        }
+    }
+
+    fn hit(&self, state: &CycleState) -> bool {
+        match self {
+            Breakpoint::Cycle(cycle) => state.cycle == *cycle,
+            Breakpoint::Register(register, value) => state.registers.get(register) == Some(value),
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Cycle(cycle) => write!(f, "cycle {}", cycle),
+            Breakpoint::Register(register, value) => write!(f, "{}={}", register, value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    Cycle,
+    Register(char),
+    Value(i32),
+}
+
+impl Operand {
+    fn parse(text: &str) -> Option<Operand> {
+        if text == "cycle" {
+            return Some(Operand::Cycle);
+        }
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (Some(register), None) if register.is_ascii_lowercase() => {
+                Some(Operand::Register(register))
+            }
+            _ => text.parse().ok().map(Operand::Value),
+        }
+    }
+
+    fn evaluate(&self, state: &CycleState) -> i64 {
+        match self {
+            Operand::Cycle => state.cycle as i64,
+            Operand::Register(register) => *state.registers.get(register).unwrap_or(&0) as i64,
+            Operand::Value(value) => *value as i64,
+        }
+    }
+}
+
+// `cycle`, a register or a number, optionally combined with another by +, - or *
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Watch {
+    text: String,
+    left: Operand,
+    operation: Option<(char, Operand)>,
+}
+
+impl Watch {
+    fn parse(text: &str) -> Option<Watch> {
+        let text = text.split_whitespace().collect::<String>();
+        let (left, operation) = match text
+            .char_indices()
+            .skip(1)
+            .find(|(_, c)| matches!(c, '+' | '-' | '*'))
+        {
+            Some((index, operator)) => (
+                Operand::parse(&text[..index])?,
+                Some((operator, Operand::parse(&text[index + 1..])?)),
+            ),
+            None => (Operand::parse(&text)?, None),
+        };
+        Some(Watch {
+            text,
+            left,
+            operation,
+        })
+    }
+
+    fn evaluate(&self, state: &CycleState) -> i64 {
+        let left = self.left.evaluate(state);
+        match &self.operation {
+            None => left,
+            Some(('+', right)) => left + right.evaluate(state),
+            Some(('-', right)) => left - right.evaluate(state),
+            Some((_, right)) => left * right.evaluate(state),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum DebuggerError {
+    UnknownCommand(String),
+    InvalidBreakpoint(String),
+    InvalidWatch(String),
+    InvalidCount(String),
+}
+
+impl fmt::Display for DebuggerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DebuggerError::UnknownCommand(command) => write!(f, "Unknown command {:?}", command),
+            DebuggerError::InvalidBreakpoint(text) => {
+                write!(
+                    f,
+                    "Invalid breakpoint {:?}, expected a cycle or register=value",
+                    text
+                )
+            }
+            DebuggerError::InvalidWatch(text) => write!(f, "Invalid watch expression {:?}", text),
+            DebuggerError::InvalidCount(text) => write!(f, "Invalid step count {:?}", text),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::Noop => write!(f, "noop"),
+            Instruction::Add(register, value) => write!(f, "add{} {}", register, value),
+            Instruction::Custom(name, args) => {
+                write!(f, "{}", name)?;
+                args.iter().try_for_each(|arg| write!(f, " {}", arg))
+            }
+        }
+    }
+}
+
+// Runs a program cycle by cycle under the control of text commands
+struct Debugger<'a> {
+    program: Vec<Instruction>,
+    cpu: Peekable<Cpu<'a>>,
+    state: Option<CycleState>,
+    breakpoints: Vec<Breakpoint>,
+    watches: Vec<Watch>,
+    crt: Vec<char>,
+}
+
+impl<'a> Debugger<'a> {
+    fn new(instruction_set: &'a InstructionSet, program: Vec<Instruction>) -> Debugger<'a> {
+        Debugger {
+            cpu: Cpu::new(instruction_set, program.clone()).peekable(),
+            program,
+            state: None,
+            breakpoints: Vec::new(),
+            watches: Vec::new(),
+            crt: Vec::new(),
+        }
+    }
+
+    fn step(&mut self) -> bool {
+        match self.cpu.next() {
+            Some(state) => {
+                self.crt.push(pixel(&state));
+                self.state = Some(state);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Steps through the remaining cycles of the next instruction
+    fn step_instruction(&mut self) -> bool {
+        if !self.step() {
+            return false;
+        }
+        let instruction = self.state.as_ref().unwrap().instruction;
+        while self
+            .cpu
+            .peek()
+            .is_some_and(|next| next.instruction == instruction)
+        {
+            self.step();
+        }
+        true
+    }
+
+    fn run_until_break(&mut self) -> Option<usize> {
+        while self.step() {
+            let state = self.state.as_ref().unwrap();
+            if let Some(index) = self.breakpoints.iter().position(|b| b.hit(state)) {
+                return Some(index);
+            }
+        }
+        None
+    }
+
+    fn status(&self) -> Vec<String> {
+        let Some(state) = &self.state else {
+            return vec!["Not started".to_string()];
+        };
+        let registers = state
+            .registers
+            .iter()
+            .map(|(register, value)| format!("{}={}", register, value))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let mut lines = vec![format!(
+            "Cycle {}: {} ({})",
+            state.cycle, registers, self.program[state.instruction]
+        )];
+        lines.extend(
+            self.watches
+                .iter()
+                .map(|watch| format!("  {} = {}", watch.text, watch.evaluate(state))),
+        );
+        lines
+    }
+
+    fn finished(&self) -> Vec<String> {
+        let cycles = self.state.as_ref().map_or(0, |state| state.cycle);
+        vec![format!("Program finished after {} cycles", cycles)]
+    }
+
+    // The CRT row being drawn, along with the sprite position on it
+    fn crt_row(&self) -> Vec<String> {
+        let Some(state) = &self.state else {
+            return vec!["Not started".to_string()];
+        };
+        let row = (state.cycle - 1) / 40;
+        let sprite = (0..40)
+            .map(|column| {
+                if (state.x() - column).abs() < 2 {
+                    '#'
+                } else {
+                    '.'
+                }
+            })
+            .collect::<String>();
+        vec![
+            format!(
+                "Row {:<3} {}",
+                row,
+                self.crt[row * 40..].iter().collect::<String>()
+            ),
+            format!("Sprite  {}", sprite),
+        ]
+    }
+
+    fn execute(&mut self, command: &str) -> Result<Vec<String>, DebuggerError> {
+        let (name, argument) = command
+            .trim()
+            .split_once(' ')
+            .unwrap_or((command.trim(), ""));
+        let count = || match argument.trim() {
+            "" => Ok(1),
+            count => count
+                .parse::<usize>()
+                .map_err(|_| DebuggerError::InvalidCount(count.to_string())),
+        };
+        match name {
+            "step" | "s" => {
+                for _ in 0..count()? {
+                    if !self.step() {
+                        return Ok(self.finished());
+                    }
+                }
+                Ok(self.status())
+            }
+            "next" | "n" => {
+                for _ in 0..count()? {
+                    if !self.step_instruction() {
+                        return Ok(self.finished());
+                    }
+                }
+                Ok(self.status())
+            }
+            "continue" | "c" => match self.run_until_break() {
+                Some(index) => {
+                    let mut lines = vec![format!(
+                        "Breakpoint {}: {}",
+                        index + 1,
+                        self.breakpoints[index]
+                    )];
+                    lines.extend(self.status());
+                    Ok(lines)
+                }
+                None => Ok(self.finished()),
+            },
+            "break" | "b" => {
+                let breakpoint = Breakpoint::parse(argument)
+                    .ok_or_else(|| DebuggerError::InvalidBreakpoint(argument.to_string()))?;
+                self.breakpoints.push(breakpoint);
+                Ok(vec![format!(
+                    "Breakpoint {}: {}",
+                    self.breakpoints.len(),
+                    self.breakpoints.last().unwrap()
+                )])
+            }
+            "watch" | "w" => {
+                let watch = Watch::parse(argument)
+                    .ok_or_else(|| DebuggerError::InvalidWatch(argument.to_string()))?;
+                self.watches.push(watch);
+                Ok(vec![format!(
+                    "Watch {}: {}",
+                    self.watches.len(),
+                    self.watches.last().unwrap().text
+                )])
+            }
+            "print" | "p" => Ok(self.status()),
+            "crt" => Ok(self.crt_row()),
+            _ => Err(DebuggerError::UnknownCommand(command.trim().to_string())),
+        }
+    }
+
+    // Runs one command per line, reporting errors in the output instead of stopping
+    fn run_script(&mut self, script: &str) -> String {
+        script
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .flat_map(|line| match self.execute(line) {
+                Ok(lines) => lines,
+                Err(error) => vec![error.to_string()],
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
 }
 
 #[test]
 fn test_example() {
     let input = include_str!("../example.txt");
-    assert_eq!(13140, part_1(input));
+    assert_eq!(Ok(13140), part_1(input));
+    assert_eq!(
+        Ok([
+            "##..##..##..##..##..##..##..##..##..##..",
+            "###...###...###...###...###...###...###.",
+            "####....####....####....####....####....",
+            "#####.....#####.....#####.....#####.....",
+            "######......######......######......####",
+            "#######.......#######.......#######.....",
+        ]
+        .join("\n")),
+        part_2(input)
+    );
+}
+
+#[cfg(test)]
+struct Multiply;
+
+#[cfg(test)]
+impl Opcode for Multiply {
+    fn cycles(&self) -> usize {
+        3
+    }
+
+    // mul <factor>: x *= factor
+    fn execute(&self, registers: &mut Registers, args: &[i32]) {
+        *registers.get_mut(&'x').unwrap() *= args[0];
+    }
+}
+
+#[cfg(test)]
+struct Swap;
+
+#[cfg(test)]
+impl Opcode for Swap {
+    fn cycles(&self) -> usize {
+        0
+    }
+
+    // swp: exchange x and y
+    fn execute(&self, registers: &mut Registers, _args: &[i32]) {
+        let x = registers[&'x'];
+        let y = registers.insert('y', x).unwrap_or(0);
+        registers.insert('x', y);
+    }
+}
+
+#[test]
+fn test_custom_opcodes() {
+    let mut instruction_set = InstructionSet::default();
+    instruction_set.register("mul", Box::new(Multiply));
+    instruction_set.register("swp", Box::new(Swap));
+    let program = instruction_set.decode("addy 5\nmul 3\nswp\nnoop").unwrap();
+    assert_eq!(
+        vec![
+            Instruction::Add('y', 5),
+            Instruction::Custom(String::from("mul"), vec![3]),
+            Instruction::Custom(String::from("swp"), vec![]),
+            Instruction::Noop
+        ],
+        program
+    );
+
+    let states = Cpu::new(&instruction_set, program).collect::<Vec<CycleState>>();
+    assert_eq!(
+        vec![
+            (1, 0, 0),
+            (1, 0, 0),
+            (1, 5, 1),
+            (1, 5, 1),
+            (1, 5, 1),
+            (5, 3, 3)
+        ],
+        states
+            .iter()
+            .map(|state| {
+                let y = state.registers.get(&'y').copied().unwrap_or(0);
+                (state.x(), y, state.instruction)
+            })
+            .collect::<Vec<(i32, i32, usize)>>()
+    );
+    assert_eq!(6, states.last().unwrap().cycle);
+
+    assert_eq!(
+        Err(CpuError::UnknownInstruction {
+            line: 2,
+            text: String::from("mul 3")
+        }),
+        part_1("noop\nmul 3")
+    );
+}
+
+#[test]
+fn test_probes() {
+    let input = include_str!("../example.txt");
+    let report = measure_signal(input, &Probes::Cycles(vec![220, 20])).unwrap();
+    assert_eq!(
+        vec![
+            Probe {
+                cycle: 220,
+                x: 18,
+                signal_strength: 3960
+            },
+            Probe {
+                cycle: 20,
+                x: 21,
+                signal_strength: 420
+            }
+        ],
+        report.probes
+    );
+    assert_eq!(4380, report.sum);
+
+    let every = Probes::Every {
+        start: 1,
+        step: 1,
+        count: 5,
+    };
+    assert_eq!(vec![1, 2, 3, 4, 5], every.cycles());
+    assert_eq!(
+        Ok(1 + 2 + 3 + 4 * 4 + 5 * 4),
+        measure_signal("noop\naddx 3\naddx -5", &every).map(|r| r.sum)
+    );
+
+    assert_eq!(
+        Err(CpuError::ProgramTooShort {
+            probe: 20,
+            cycles: 3
+        }),
+        part_1("noop\naddx 3")
+    );
+    assert_eq!(
+        Err(CpuError::ProgramTooShort {
+            probe: 0,
+            cycles: 1
+        }),
+        measure_signal("noop", &Probes::Cycles(vec![0]))
+    );
+}
+
+#[test]
+fn test_read_letters() {
+    assert_eq!(
+        Ok(String::from("PLPAFBCL")),
+        read_letters(&part_2(include_str!("../input.txt")).unwrap())
+    );
+
+    let image = FONT
+        .iter()
+        .fold(vec![String::new(); 6], |mut rows, (_, pattern)| {
+            for (row, line) in rows.iter_mut().zip(pattern) {
+                *row += line;
+                *row += ".";
+            }
+            rows
+        })
+        .join("\n");
+    assert_eq!(
+        Ok(FONT.iter().map(|(letter, _)| letter).collect::<String>()),
+        read_letters(&image)
+    );
+
+    assert_eq!(
+        Err(OcrError::UnknownGlyph {
+            index: 0,
+            column: 0,
+            glyph: String::from("##..\n###.\n####\n####\n####\n####")
+        }),
+        read_letters(&part_2(include_str!("../example.txt")).unwrap())
+    );
+    assert_eq!(
+        Err(OcrError::InvalidImage { rows: 1 }),
+        read_letters("####")
+    );
+}
+
+#[test]
+fn test_debugger() {
+    let instruction_set = InstructionSet::default();
+    let program = instruction_set
+        .decode(include_str!("../example.txt"))
+        .unwrap();
+    let mut debugger = Debugger::new(&instruction_set, program);
+    assert_eq!(
+        [
+            "Not started",
+            "Breakpoint 1: cycle 20",
+            "Watch 1: cycle*x",
+            "Breakpoint 1: cycle 20",
+            "Cycle 20: x=21 (addx -1)",
+            "  cycle*x = 420",
+        ]
+        .join("\n"),
+        debugger.run_script("print\nbreak 20\nwatch cycle * x\ncontinue")
+    );
+    assert_eq!(
+        [
+            "Cycle 21: x=21 (addx -1)",
+            "  cycle*x = 441",
+            "Cycle 25: x=25 (addx -1)",
+            "  cycle*x = 625",
+        ]
+        .join("\n"),
+        debugger.run_script("step\n# comment\nnext 2")
+    );
+    assert_eq!(
+        [
+            "Breakpoint 2: x=-1",
+            "Breakpoint 2: x=-1",
+            "Cycle 210: x=-1 (addx 22)",
+            "  cycle*x = -210",
+            "Row 5   #######...",
+            "Sprite  #.......................................",
+        ]
+        .join("\n"),
+        debugger.run_script("b x=-1\nc\ncrt")
+    );
+    assert_eq!(
+        Err(DebuggerError::InvalidBreakpoint("x=".to_string())),
+        debugger.execute("break x=")
+    );
+    assert_eq!(
+        Err(DebuggerError::UnknownCommand("jump 3".to_string())),
+        debugger.execute("jump 3")
+    );
+    assert_eq!(
+        "Program finished after 240 cycles",
+        debugger.run_script("step 1000")
+    );
 }
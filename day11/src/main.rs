@@ -1,26 +1,218 @@
+use num_bigint::BigUint;
+use std::fmt;
+
 fn main() {
-    println!("{}", part_1(get_input()));
-    println!("{}", part_2(get_input()));
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    if !args.is_empty() {
+        return run(&args);
+    }
+    match parse_monkeys(include_str!("../input.txt")) {
+        Ok(monkeys) => match (part_1(monkeys.clone()), part_2(monkeys)) {
+            (Ok(part_1), Ok(part_2)) => println!("{}\n{}", part_1, part_2),
+            (Err(error), _) | (_, Err(error)) => println!("{}", error),
+        },
+        Err(error) => println!("{}", error),
+    }
 }
 
-#[derive(Copy, Clone)]
+// The right-hand side of `new = ...`, built from `old`, integers, +, -, * and parentheses
+#[derive(Debug, Clone, PartialEq, Eq)]
 enum Operation {
-    Add(u64),
-    Mult(u64),
-    Square,
+    Old,
+    Value(u64),
+    Add(Box<Operation>, Box<Operation>),
+    Sub(Box<Operation>, Box<Operation>),
+    Mult(Box<Operation>, Box<Operation>),
 }
 
 impl Operation {
-    fn value(&self, old: u64) -> u64 {
+    // None if the worry level leaves the range of u64
+    fn value(&self, old: u64) -> Option<u64> {
+        match self {
+            Operation::Old => Some(old),
+            Operation::Value(v) => Some(*v),
+            Operation::Add(a, b) => a.value(old)?.checked_add(b.value(old)?),
+            Operation::Sub(a, b) => a.value(old)?.checked_sub(b.value(old)?),
+            Operation::Mult(a, b) => a.value(old)?.checked_mul(b.value(old)?),
+        }
+    }
+
+    // Addition and multiplication respect the modulus, so the result is the true value reduced
+    // modulo `modulus`. Subtraction is refused: the true value could have gone negative, which
+    // the reduced value cannot tell.
+    fn value_modulo(&self, old: u64, modulus: u64) -> Option<u64> {
+        let m = modulus as u128;
+        let value = match self {
+            Operation::Old => old as u128 % m,
+            Operation::Value(v) => *v as u128 % m,
+            Operation::Add(a, b) => {
+                (a.value_modulo(old, modulus)? as u128 + b.value_modulo(old, modulus)? as u128) % m
+            }
+            Operation::Sub(_, _) => return None,
+            Operation::Mult(a, b) => {
+                (a.value_modulo(old, modulus)? as u128 * b.value_modulo(old, modulus)? as u128) % m
+            }
+        };
+        Some(value as u64)
+    }
+
+    fn respects_modulus(&self) -> bool {
+        match self {
+            Operation::Old | Operation::Value(_) => true,
+            Operation::Add(a, b) | Operation::Mult(a, b) => {
+                a.respects_modulus() && b.respects_modulus()
+            }
+            Operation::Sub(_, _) => false,
+        }
+    }
+
+    // None if the worry level would become negative
+    fn value_exact(&self, old: &BigUint) -> Option<BigUint> {
         match self {
-            Operation::Add(v) => v + old,
-            Operation::Mult(v) => v * old,
-            Operation::Square => old * old,
+            Operation::Old => Some(old.clone()),
+            Operation::Value(v) => Some(BigUint::from(*v)),
+            Operation::Add(a, b) => Some(&a.value_exact(old)? + &b.value_exact(old)?),
+            Operation::Sub(a, b) => {
+                let (a, b) = (a.value_exact(old)?, b.value_exact(old)?);
+                (a >= b).then(|| a - b)
+            }
+            Operation::Mult(a, b) => Some(&a.value_exact(old)? * &b.value_exact(old)?),
         }
     }
 }
 
-#[derive(Clone)]
+// The worry levels a game can be played with
+trait WorryLevel: Clone + fmt::Display + From<u64> {
+    fn divisible_by(&self, divisor: u64) -> bool;
+}
+
+impl WorryLevel for u64 {
+    fn divisible_by(&self, divisor: u64) -> bool {
+        self.is_multiple_of(divisor)
+    }
+}
+
+impl WorryLevel for BigUint {
+    fn divisible_by(&self, divisor: u64) -> bool {
+        self % divisor == BigUint::ZERO
+    }
+}
+
+// Options `--rounds N`, `--relief divide:K|modulo|none|custom:EXPR` and
+// `--metric top-product:K|top-sum:K|sum` set the rules, which start out as those of part 1. A custom
+// relief is an expression in `old`, like the operations in the notes. The options can be followed
+// by `snapshots ROUND...` or `trace csv|json` to record the game instead of scoring it.
+fn run(args: &[String]) {
+    let monkeys = match parse_monkeys(include_str!("../input.txt")) {
+        Ok(monkeys) => monkeys,
+        Err(error) => return println!("{}", error),
+    };
+    let mut rules = Rules::part_1();
+    let mut round_count = None;
+    let mut command = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let option = arg.as_str();
+        if !matches!(option, "--rounds" | "--relief" | "--metric") {
+            command.push(option);
+            continue;
+        }
+        let value = args.next().map(String::as_str).unwrap_or("");
+        match option {
+            "--rounds" => match value.parse() {
+                Ok(rounds) => round_count = Some(rounds),
+                Err(_) => return println!("Rounds must be a number, not {:?}", value),
+            },
+            "--relief" => match parse_relief(value) {
+                Some(relief) => rules.relief = relief,
+                None => {
+                    return println!(
+                        "Unknown relief {:?}, expected divide:K, modulo, none or custom:EXPR",
+                        value
+                    )
+                }
+            },
+            _ => match parse_metric(value) {
+                Some(metric) => rules.metric = metric,
+                None => {
+                    return println!(
+                        "Unknown metric {:?}, expected top-product:K, top-sum:K or sum",
+                        value
+                    )
+                }
+            },
+        }
+    }
+    let recording = match command.as_slice() {
+        [] => Recording::default(),
+        ["snapshots", rounds @ ..] => {
+            match rounds
+                .iter()
+                .map(|arg| arg.parse().map_err(|_| arg))
+                .collect()
+            {
+                Ok(snapshot_rounds) => Recording {
+                    snapshot_rounds,
+                    trace: false,
+                },
+                Err(arg) => return println!("Snapshot rounds must be numbers, not {:?}", arg),
+            }
+        }
+        ["trace"] | ["trace", "csv" | "json"] => Recording {
+            snapshot_rounds: vec![],
+            trace: true,
+        },
+        _ => return println!("Unknown command {:?}", command.join(" ")),
+    };
+    rules.round_count = round_count
+        .or(recording.snapshot_rounds.iter().copied().max())
+        .unwrap_or(rules.round_count);
+    match play(monkeys, &rules, &recording) {
+        Ok(Played::Bounded(simulation)) => report(&simulation, &rules, &command),
+        Ok(Played::Exact(simulation)) => report(&simulation, &rules, &command),
+        Err(error) => println!("{}", error),
+    }
+}
+
+fn parse_relief(text: &str) -> Option<Relief> {
+    match text.split_once(':') {
+        Some(("divide", k)) => k.parse().ok().filter(|&k| k > 0).map(Relief::Divide),
+        None if text == "modulo" => Some(Relief::Modulo),
+        None if text == "none" => Some(Relief::None),
+        Some(("custom", expression)) => parse_operation(0, expression)
+            .ok()
+            .map(|operation| Relief::custom(move |old| operation.value_exact(old))),
+        _ => None,
+    }
+}
+
+fn parse_metric(text: &str) -> Option<Metric> {
+    match text.split_once(':') {
+        Some(("top-product", k)) => k.parse().ok().map(Metric::TopProduct),
+        Some(("top-sum", k)) => k.parse().ok().map(Metric::TopSum),
+        None if text == "sum" => Some(Metric::Sum),
+        _ => None,
+    }
+}
+
+fn report<W: WorryLevel>(simulation: &Simulation<W>, rules: &Rules, command: &[&str]) {
+    match command {
+        ["snapshots", ..] => {
+            for snapshot in &simulation.snapshots {
+                println!("{}\n", render_snapshot(snapshot));
+            }
+            println!("{}", inspection_history(&simulation.snapshots));
+        }
+        ["trace", "json"] => println!("{}", trace_json(&simulation.trace)),
+        ["trace", ..] => println!("{}", trace_csv(&simulation.trace)),
+        _ => match rules.metric.score(&simulation.inspections) {
+            Some(score) => println!("{}", score),
+            None => println!("{}", GameError::ScoreOverflow),
+        },
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct Monkey {
     items: Vec<u64>,
     operation_mode: Operation,
@@ -29,108 +221,393 @@ struct Monkey {
     false_monkey_index: usize,
 }
 
-#[allow(dead_code)]
-fn get_example() -> Vec<Monkey> {
-    vec![
-        Monkey {
-            items: vec![79, 98],
-            operation_mode: Operation::Mult(19),
-            division_test: 23,
-            true_monkey_index: 2,
-            false_monkey_index: 3,
-        },
-        Monkey {
-            items: vec![54, 65, 75, 74],
-            operation_mode: Operation::Add(6),
-            division_test: 19,
-            true_monkey_index: 2,
-            false_monkey_index: 0,
-        },
-        Monkey {
-            items: vec![79, 60, 97],
-            operation_mode: Operation::Square,
-            division_test: 13,
-            true_monkey_index: 1,
-            false_monkey_index: 3,
-        },
-        Monkey {
-            items: vec![74],
-            operation_mode: Operation::Add(3),
-            division_test: 17,
-            true_monkey_index: 0,
-            false_monkey_index: 1,
-        },
-    ]
+#[derive(Debug, PartialEq, Eq)]
+enum NotesError {
+    MissingLine {
+        line: usize,
+        expected: &'static str,
+    },
+    UnexpectedLine {
+        line: usize,
+        expected: &'static str,
+        text: String,
+    },
+    InvalidNumber {
+        line: usize,
+        text: String,
+    },
+    UnsupportedOperation {
+        line: usize,
+        text: String,
+    },
+    WrongMonkeyIndex {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    UnknownTarget {
+        monkey: usize,
+        target: usize,
+    },
+    ZeroDivisor {
+        line: usize,
+    },
+    NoMonkeys,
 }
 
-// No parsing today
-fn get_input() -> Vec<Monkey> {
-    vec![
-        Monkey {
-            items: vec![99, 67, 92, 61, 83, 64, 98],
-            operation_mode: Operation::Mult(17),
-            division_test: 3,
-            true_monkey_index: 4,
-            false_monkey_index: 2,
-        },
-        Monkey {
-            items: vec![78, 74, 88, 89, 50],
-            operation_mode: Operation::Mult(11),
-            division_test: 5,
-            true_monkey_index: 3,
-            false_monkey_index: 5,
-        },
-        Monkey {
-            items: vec![98, 91],
-            operation_mode: Operation::Add(4),
-            division_test: 2,
-            true_monkey_index: 6,
-            false_monkey_index: 4,
-        },
-        Monkey {
-            items: vec![59, 72, 94, 91, 79, 88, 94, 51],
-            operation_mode: Operation::Square,
-            division_test: 13,
-            true_monkey_index: 0,
-            false_monkey_index: 5,
-        },
-        Monkey {
-            items: vec![95, 72, 78],
-            operation_mode: Operation::Add(7),
-            division_test: 11,
-            true_monkey_index: 7,
-            false_monkey_index: 6,
-        },
-        Monkey {
-            items: vec![76],
-            operation_mode: Operation::Add(8),
-            division_test: 17,
-            true_monkey_index: 0,
-            false_monkey_index: 2,
-        },
-        Monkey {
-            items: vec![69, 60, 53, 89, 71, 88],
-            operation_mode: Operation::Add(5),
-            division_test: 19,
-            true_monkey_index: 7,
-            false_monkey_index: 1,
-        },
-        Monkey {
-            items: vec![72, 54, 63, 80],
-            operation_mode: Operation::Add(3),
-            division_test: 7,
-            true_monkey_index: 1,
-            false_monkey_index: 3,
-        },
-    ]
+impl fmt::Display for NotesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NotesError::MissingLine { line, expected } => {
+                write!(
+                    f,
+                    "Line {}: expected {:?}, found the end of the notes",
+                    line, expected
+                )
+            }
+            NotesError::UnexpectedLine {
+                line,
+                expected,
+                text,
+            } => {
+                write!(
+                    f,
+                    "Line {}: expected {:?}, found {:?}",
+                    line, expected, text
+                )
+            }
+            NotesError::InvalidNumber { line, text } => {
+                write!(f, "Line {}: invalid number {:?}", line, text)
+            }
+            NotesError::UnsupportedOperation { line, text } => {
+                write!(f, "Line {}: unsupported operation {:?}", line, text)
+            }
+            NotesError::WrongMonkeyIndex {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Line {}: expected monkey {}, found monkey {}",
+                line, expected, found
+            ),
+            NotesError::UnknownTarget { monkey, target } => {
+                write!(
+                    f,
+                    "Monkey {} throws to monkey {}, which does not exist",
+                    monkey, target
+                )
+            }
+            NotesError::ZeroDivisor { line } => {
+                write!(f, "Line {}: the test cannot divide by zero", line)
+            }
+            NotesError::NoMonkeys => write!(f, "The notes describe no monkeys"),
+        }
+    }
+}
+
+const MONKEY_LINES: [&str; 6] = [
+    "Monkey ",
+    "Starting items:",
+    "Operation: new =",
+    "Test: divisible by",
+    "If true: throw to monkey",
+    "If false: throw to monkey",
+];
+
+fn parse_number<T: std::str::FromStr>(line: usize, text: &str) -> Result<T, NotesError> {
+    text.trim().parse().map_err(|_| NotesError::InvalidNumber {
+        line,
+        text: text.trim().to_string(),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Old,
+    Number(u64),
+    Symbol(char),
+}
+
+fn tokenize(text: &str) -> Option<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() {
+            let mut number = 0u64;
+            while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                number = number.checked_mul(10)?.checked_add(digit as u64)?;
+                chars.next();
+            }
+            tokens.push(Token::Number(number));
+        } else if c.is_ascii_alphabetic() {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphanumeric()) {
+                word.push(c);
+                chars.next();
+            }
+            if word != "old" {
+                return None;
+            }
+            tokens.push(Token::Old);
+        } else if "+-*()".contains(c) {
+            tokens.push(Token::Symbol(c));
+            chars.next();
+        } else {
+            return None;
+        }
+    }
+    Some(tokens)
+}
+
+// Recursive descent, with * binding tighter than + and -
+fn parse_sum(tokens: &[Token], position: &mut usize) -> Option<Operation> {
+    let mut operation = parse_product(tokens, position)?;
+    while let Some(Token::Symbol(symbol @ ('+' | '-'))) = tokens.get(*position) {
+        *position += 1;
+        let right = Box::new(parse_product(tokens, position)?);
+        operation = if *symbol == '+' {
+            Operation::Add(Box::new(operation), right)
+        } else {
+            Operation::Sub(Box::new(operation), right)
+        };
+    }
+    Some(operation)
+}
+
+fn parse_product(tokens: &[Token], position: &mut usize) -> Option<Operation> {
+    let mut operation = parse_term(tokens, position)?;
+    while let Some(Token::Symbol('*')) = tokens.get(*position) {
+        *position += 1;
+        operation = Operation::Mult(Box::new(operation), Box::new(parse_term(tokens, position)?));
+    }
+    Some(operation)
+}
+
+fn parse_term(tokens: &[Token], position: &mut usize) -> Option<Operation> {
+    let token = *tokens.get(*position)?;
+    *position += 1;
+    match token {
+        Token::Old => Some(Operation::Old),
+        Token::Number(value) => Some(Operation::Value(value)),
+        Token::Symbol('(') => {
+            let operation = parse_sum(tokens, position)?;
+            if tokens.get(*position) != Some(&Token::Symbol(')')) {
+                return None;
+            }
+            *position += 1;
+            Some(operation)
+        }
+        Token::Symbol(_) => None,
+    }
+}
+
+fn parse_operation(line: usize, text: &str) -> Result<Operation, NotesError> {
+    let unsupported = || NotesError::UnsupportedOperation {
+        line,
+        text: text.trim().to_string(),
+    };
+    let tokens = tokenize(text).ok_or_else(unsupported)?;
+    let mut position = 0;
+    let operation = parse_sum(&tokens, &mut position).ok_or_else(unsupported)?;
+    if position != tokens.len() {
+        return Err(unsupported());
+    }
+    Ok(operation)
+}
+
+fn parse_monkeys(input: &str) -> Result<Vec<Monkey>, NotesError> {
+    let lines = input
+        .lines()
+        .enumerate()
+        .map(|(index, text)| (index + 1, text.trim()))
+        .filter(|(_, text)| !text.is_empty())
+        .collect::<Vec<(usize, &str)>>();
+    let last_line = input.lines().count();
+    let monkeys = lines
+        .chunks(MONKEY_LINES.len())
+        .enumerate()
+        .map(|(index, chunk)| {
+            // The text after the expected prefix of each line of the block
+            let fields = MONKEY_LINES
+                .iter()
+                .enumerate()
+                .map(|(i, expected)| match chunk.get(i) {
+                    Some((line, text)) => text
+                        .strip_prefix(expected)
+                        .map(|field| (*line, field))
+                        .ok_or_else(|| NotesError::UnexpectedLine {
+                            line: *line,
+                            expected,
+                            text: text.to_string(),
+                        }),
+                    None => Err(NotesError::MissingLine {
+                        line: last_line + 1,
+                        expected,
+                    }),
+                })
+                .collect::<Result<Vec<(usize, &str)>, NotesError>>()?;
+            let (line, header) = fields[0];
+            let found = parse_number(line, header.strip_suffix(':').unwrap_or(header))?;
+            if found != index {
+                return Err(NotesError::WrongMonkeyIndex {
+                    line,
+                    expected: index,
+                    found,
+                });
+            }
+            let (line, divisor) = fields[3];
+            let division_test = parse_number(line, divisor)?;
+            if division_test == 0 {
+                return Err(NotesError::ZeroDivisor { line });
+            }
+            let (line, items) = fields[1];
+            Ok(Monkey {
+                items: items
+                    .split(',')
+                    .filter(|item| !item.trim().is_empty())
+                    .map(|item| parse_number(line, item))
+                    .collect::<Result<Vec<u64>, NotesError>>()?,
+                operation_mode: parse_operation(fields[2].0, fields[2].1)?,
+                division_test,
+                true_monkey_index: parse_number(fields[4].0, fields[4].1)?,
+                false_monkey_index: parse_number(fields[5].0, fields[5].1)?,
+            })
+        })
+        .collect::<Result<Vec<Monkey>, NotesError>>()?;
+    if monkeys.is_empty() {
+        return Err(NotesError::NoMonkeys);
+    }
+    for (index, monkey) in monkeys.iter().enumerate() {
+        for target in [monkey.true_monkey_index, monkey.false_monkey_index] {
+            if target >= monkeys.len() {
+                return Err(NotesError::UnknownTarget {
+                    monkey: index,
+                    target,
+                });
+            }
+        }
+    }
+    Ok(monkeys)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum GameError {
+    WorryOverflow {
+        round: usize,
+        monkey: usize,
+        worry_level: String,
+    },
+    ScoreOverflow,
+    IncompatibleOperation {
+        monkey: usize,
+    },
+    NoMonkeys,
+    ModulusOverflow,
 }
 
-fn part_1(setup: Vec<Monkey>) -> u64 {
-    perform_rounds(setup, 20, true)
+impl fmt::Display for GameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameError::WorryOverflow {
+                round,
+                monkey,
+                worry_level,
+            } => write!(
+                f,
+                "Round {}: the operation of monkey {} leaves the range of worry levels for an item at {}",
+                round, monkey, worry_level
+            ),
+            GameError::ScoreOverflow => write!(f, "The level of monkey business is too large"),
+            GameError::IncompatibleOperation { monkey } => write!(
+                f,
+                "The operation of monkey {} subtracts, so worry levels cannot be reduced modulo the tests",
+                monkey
+            ),
+            GameError::NoMonkeys => write!(f, "There are no monkeys to play with"),
+            GameError::ModulusOverflow => {
+                write!(f, "The tests have no common multiple small enough to reduce worry levels by")
+            }
+        }
+    }
 }
 
-fn part_2(setup: Vec<Monkey>) -> u64 {
-    perform_rounds(setup, 10000, false)
+// What happens to the worry level after a monkey inspects an item
+enum Relief {
+    Divide(u64),
+    // Reducing modulo the LCM of the tests keeps every test outcome the same
+    Modulo,
+    // Worry levels are followed exactly, as big integers
+    None,
+    // Applied to the exact worry level, None if the result cannot be a worry level
+    Custom(Relieve),
+}
+
+type Relieve = Box<dyn Fn(&BigUint) -> Option<BigUint>>;
+
+impl Relief {
+    fn custom(relieve: impl Fn(&BigUint) -> Option<BigUint> + 'static) -> Relief {
+        Relief::Custom(Box::new(relieve))
+    }
+}
+
+// How the inspection counts become the level of monkey business
+enum Metric {
+    TopProduct(usize),
+    TopSum(usize),
+    Sum,
+}
+
+impl Metric {
+    fn score(&self, inspections: &[u64]) -> Option<u64> {
+        let mut counts = inspections.to_vec();
+        counts.sort_unstable_by(|a, b| b.cmp(a));
+        let (take, product) = match self {
+            Metric::TopProduct(k) => (*k, true),
+            Metric::TopSum(k) => (*k, false),
+            Metric::Sum => (counts.len(), false),
+        };
+        let mut counts = counts.into_iter().take(take);
+        if product {
+            counts.try_fold(1, u64::checked_mul)
+        } else {
+            counts.try_fold(0, u64::checked_add)
+        }
+    }
+}
+
+struct Rules {
+    round_count: usize,
+    relief: Relief,
+    metric: Metric,
+}
+
+impl Rules {
+    fn part_1() -> Rules {
+        Rules {
+            round_count: 20,
+            relief: Relief::Divide(3),
+            metric: Metric::TopProduct(2),
+        }
+    }
+
+    fn part_2() -> Rules {
+        Rules {
+            round_count: 10000,
+            relief: Relief::Modulo,
+            metric: Metric::TopProduct(2),
+        }
+    }
+}
+
+fn part_1(setup: Vec<Monkey>) -> Result<u64, GameError> {
+    monkey_business(setup, &Rules::part_1())
+}
+
+fn part_2(setup: Vec<Monkey>) -> Result<u64, GameError> {
+    monkey_business(setup, &Rules::part_2())
 }
 
 fn gcd(a: u64, b: u64) -> u64 {
@@ -141,58 +618,562 @@ fn gcd(a: u64, b: u64) -> u64 {
     }
 }
 
-fn lcm(a: u64, b: u64) -> u64 {
-    a * b / gcd(a, b)
+// None if the LCM does not fit in a u64
+fn lcm(a: u64, b: u64) -> Option<u64> {
+    (a / gcd(a, b)).checked_mul(b)
 }
 
-fn perform_rounds(setup: Vec<Monkey>, round_count: usize, part_1: bool) -> u64 {
-    let mut monkeys = setup;
-    let mut inspection_counter = vec![];
-    inspection_counter.resize(monkeys.len(), 0);
-    let lcm = monkeys.iter().map(|m| m.division_test).reduce(lcm).unwrap();
+// What to record while the monkeys play
+#[derive(Default)]
+struct Recording {
+    snapshot_rounds: Vec<usize>,
+    trace: bool,
+}
+
+// The items and inspection counts at the end of a round
+#[derive(Debug, PartialEq, Eq)]
+struct Snapshot<W> {
+    round: usize,
+    items: Vec<Vec<W>>,
+    inspections: Vec<u64>,
+}
 
-    for _round in 0..round_count {
-        for i in 0..monkeys.len() {
-            let current_monkey = monkeys[i].clone();
-            let items = current_monkey.items;
-            monkeys[i].items = vec![];
-            for item in items {
+// Items are numbered in the order they appear in the notes
+#[derive(Debug, PartialEq, Eq)]
+struct Throw<W> {
+    round: usize,
+    item: usize,
+    from: usize,
+    to: usize,
+    worry_level: W,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Simulation<W> {
+    inspections: Vec<u64>,
+    snapshots: Vec<Snapshot<W>>,
+    trace: Vec<Throw<W>>,
+}
+
+fn monkey_business(setup: Vec<Monkey>, rules: &Rules) -> Result<u64, GameError> {
+    let played = play(setup, rules, &Recording::default())?;
+    rules
+        .metric
+        .score(played.inspections())
+        .ok_or(GameError::ScoreOverflow)
+}
+
+// Worry levels fit in a u64 under the built-in reliefs, otherwise they are followed exactly
+enum Played {
+    Bounded(Simulation<u64>),
+    Exact(Simulation<BigUint>),
+}
+
+impl Played {
+    fn inspections(&self) -> &[u64] {
+        match self {
+            Played::Bounded(simulation) => &simulation.inspections,
+            Played::Exact(simulation) => &simulation.inspections,
+        }
+    }
+}
+
+fn play(setup: Vec<Monkey>, rules: &Rules, recording: &Recording) -> Result<Played, GameError> {
+    let rounds = rules.round_count;
+    match &rules.relief {
+        Relief::Divide(k) => simulate(setup, rounds, recording, |operation, &old| {
+            operation.value(old).map(|value| value / k)
+        })
+        .map(Played::Bounded),
+        Relief::Modulo => {
+            if let Some(monkey) = setup
+                .iter()
+                .position(|monkey| !monkey.operation_mode.respects_modulus())
+            {
+                return Err(GameError::IncompatibleOperation { monkey });
+            }
+            let mut divisors = setup.iter().map(|m| m.division_test);
+            let first = divisors.next().ok_or(GameError::NoMonkeys)?;
+            let lcm = divisors
+                .try_fold(first, lcm)
+                .ok_or(GameError::ModulusOverflow)?;
+            simulate(setup, rounds, recording, |operation, &old| {
+                operation.value_modulo(old, lcm)
+            })
+            .map(Played::Bounded)
+        }
+        Relief::None => simulate(setup, rounds, recording, |operation, old| {
+            operation.value_exact(old)
+        })
+        .map(Played::Exact),
+        Relief::Custom(relieve) => simulate(setup, rounds, recording, |operation, old| {
+            operation.value_exact(old).and_then(|value| relieve(&value))
+        })
+        .map(Played::Exact),
+    }
+}
+
+// `inspect` gives the new worry level, or None if it cannot be represented
+fn simulate<W: WorryLevel>(
+    monkeys: Vec<Monkey>,
+    round_count: usize,
+    recording: &Recording,
+    inspect: impl Fn(&Operation, &W) -> Option<W>,
+) -> Result<Simulation<W>, GameError> {
+    let mut inspection_counter = vec![0; monkeys.len()];
+    // The number of each item held, alongside its worry level
+    let mut holdings = vec![];
+    let mut item_count = 0;
+    for monkey in &monkeys {
+        let numbers = item_count..item_count + monkey.items.len();
+        item_count += monkey.items.len();
+        holdings.push(
+            numbers
+                .zip(monkey.items.iter().map(|&item| W::from(item)))
+                .collect::<Vec<(usize, W)>>(),
+        );
+    }
+    let mut snapshots = vec![];
+    let mut trace = vec![];
+
+    for round in 1..=round_count {
+        for (i, current_monkey) in monkeys.iter().enumerate() {
+            for (number, item) in std::mem::take(&mut holdings[i]) {
                 inspection_counter[i] += 1;
-                let new_worry_level = if part_1 {
-                    current_monkey.operation_mode.value(item) / 3
+                let new_worry_level =
+                    inspect(&current_monkey.operation_mode, &item).ok_or_else(|| {
+                        GameError::WorryOverflow {
+                            round,
+                            monkey: i,
+                            worry_level: item.to_string(),
+                        }
+                    })?;
+                let target = if new_worry_level.divisible_by(current_monkey.division_test) {
+                    current_monkey.true_monkey_index
                 } else {
-                    current_monkey.operation_mode.value(item) % lcm
+                    current_monkey.false_monkey_index
                 };
-                if new_worry_level % current_monkey.division_test == 0 {
-                    monkeys[current_monkey.true_monkey_index]
-                        .items
-                        .push(new_worry_level);
-                } else {
-                    monkeys[current_monkey.false_monkey_index]
-                        .items
-                        .push(new_worry_level);
+                if recording.trace {
+                    trace.push(Throw {
+                        round,
+                        item: number,
+                        from: i,
+                        to: target,
+                        worry_level: new_worry_level.clone(),
+                    });
                 }
+                holdings[target].push((number, new_worry_level));
             }
         }
-        /*
-        if !part_1 && (_round+1 == 1 || ((_round+1) % 1000 == 0)) {
-            println!("After round {} the monkeys are holding items with these worry levels:", _round + 1);
-            for i in 0..monkeys.len() {
-                println!("Monkey {}: {}", i, monkeys[i].items.iter().map(|n| n.to_string()).collect::<Vec<String>>().join(", "));
-            }
-            for c in 0..inspection_counter.len() {
-                    println!("Monkey {} inspected items {} times.", c, inspection_counter[c]);
-                }
-        }*/
+        if recording.snapshot_rounds.contains(&round) {
+            snapshots.push(Snapshot {
+                round,
+                items: holdings
+                    .iter()
+                    .map(|items| items.iter().map(|(_, item)| item.clone()).collect())
+                    .collect(),
+                inspections: inspection_counter.clone(),
+            });
+        }
     }
 
-    inspection_counter.sort();
-    inspection_counter.reverse();
-    inspection_counter[0] * inspection_counter[1]
+    Ok(Simulation {
+        inspections: inspection_counter,
+        snapshots,
+        trace,
+    })
+}
+
+fn render_snapshot<W: fmt::Display>(snapshot: &Snapshot<W>) -> String {
+    let mut lines = vec![format!(
+        "After round {} the monkeys are holding items with these worry levels:",
+        snapshot.round
+    )];
+    for (i, items) in snapshot.items.iter().enumerate() {
+        let items = items
+            .iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+        lines.push(format!("Monkey {}: {}", i, items));
+    }
+    for (i, count) in snapshot.inspections.iter().enumerate() {
+        lines.push(format!("Monkey {} inspected items {} times.", i, count));
+    }
+    lines.join("\n")
+}
+
+// One row per snapshot, one column per monkey
+fn inspection_history<W>(snapshots: &[Snapshot<W>]) -> String {
+    let Some(first) = snapshots.first() else {
+        return String::new();
+    };
+    let header = (0..first.inspections.len())
+        .map(|i| format!("{:>10}", format!("Monkey {}", i)))
+        .collect::<String>();
+    let mut lines = vec![format!("{:>6}{}", "Round", header)];
+    for snapshot in snapshots {
+        let counts = snapshot
+            .inspections
+            .iter()
+            .map(|count| format!("{:>10}", count))
+            .collect::<String>();
+        lines.push(format!("{:>6}{}", snapshot.round, counts));
+    }
+    lines.join("\n")
+}
+
+fn trace_csv<W: fmt::Display>(trace: &[Throw<W>]) -> String {
+    let mut lines = vec!["round,item,from,to,worry_level".to_string()];
+    lines.extend(trace.iter().map(|throw| {
+        format!(
+            "{},{},{},{},{}",
+            throw.round, throw.item, throw.from, throw.to, throw.worry_level
+        )
+    }));
+    lines.join("\n")
+}
+
+fn trace_json<W: fmt::Display>(trace: &[Throw<W>]) -> String {
+    let throws = trace
+        .iter()
+        .map(|throw| {
+            format!(
+                "  {{\"round\": {}, \"item\": {}, \"from\": {}, \"to\": {}, \"worry_level\": {}}}",
+                throw.round, throw.item, throw.from, throw.to, throw.worry_level
+            )
+        })
+        .collect::<Vec<String>>();
+    if throws.is_empty() {
+        return "[]".to_string();
+    }
+    format!("[\n{}\n]", throws.join(",\n"))
 }
 
 #[test]
 fn test_example() {
-    assert_eq!(10605, part_1(get_example()));
-    assert_eq!(2713310158, part_2(get_example()));
+    let monkeys = parse_monkeys(include_str!("../example.txt")).unwrap();
+    assert_eq!(Ok(10605), part_1(monkeys.clone()));
+    assert_eq!(Ok(2713310158), part_2(monkeys));
+}
+
+#[test]
+fn test_parse_monkeys() {
+    let monkeys = parse_monkeys(include_str!("../example.txt")).unwrap();
+    assert_eq!(4, monkeys.len());
+    assert_eq!(
+        Monkey {
+            items: vec![79, 60, 97],
+            operation_mode: Operation::Mult(Box::new(Operation::Old), Box::new(Operation::Old)),
+            division_test: 13,
+            true_monkey_index: 1,
+            false_monkey_index: 3,
+        },
+        monkeys[2]
+    );
+    assert_eq!(
+        Monkey {
+            items: vec![],
+            operation_mode: Operation::Add(Box::new(Operation::Old), Box::new(Operation::Value(6))),
+            division_test: 19,
+            true_monkey_index: 0,
+            false_monkey_index: 0,
+        },
+        parse_monkeys(
+            "Monkey 0:\n  Starting items:\n  Operation: new = old + 6\n  Test: divisible by 19\n    If true: throw to monkey 0\n    If false: throw to monkey 0"
+        )
+        .unwrap()[0]
+    );
+
+    let notes = include_str!("../example.txt");
+    assert_eq!(
+        Err(NotesError::WrongMonkeyIndex {
+            line: 8,
+            expected: 1,
+            found: 2
+        }),
+        parse_monkeys(&notes.replacen("Monkey 1:", "Monkey 2:", 1))
+    );
+    assert_eq!(
+        Err(NotesError::UnknownTarget {
+            monkey: 3,
+            target: 4
+        }),
+        parse_monkeys(&notes.replace("If true: throw to monkey 0", "If true: throw to monkey 4"))
+    );
+    assert_eq!(
+        Err(NotesError::UnsupportedOperation {
+            line: 3,
+            text: "old / 19".to_string()
+        }),
+        parse_monkeys(&notes.replacen("old * 19", "old / 19", 1))
+    );
+    assert_eq!(
+        Err(NotesError::InvalidNumber {
+            line: 2,
+            text: "9x".to_string()
+        }),
+        parse_monkeys(&notes.replacen("98", "9x", 1))
+    );
+    assert_eq!(
+        Err(NotesError::UnexpectedLine {
+            line: 4,
+            expected: "Test: divisible by",
+            text: "Test: odd".to_string()
+        }),
+        parse_monkeys(&notes.replacen("Test: divisible by 23", "Test: odd", 1))
+    );
+    assert_eq!(
+        Err(NotesError::ZeroDivisor { line: 11 }),
+        parse_monkeys(&notes.replacen("divisible by 19", "divisible by 0", 1))
+    );
+    assert_eq!(Err(NotesError::NoMonkeys), parse_monkeys("\n\n"));
+    assert_eq!(
+        Err(NotesError::MissingLine {
+            line: 5,
+            expected: "If true: throw to monkey"
+        }),
+        parse_monkeys(&notes.lines().take(4).collect::<Vec<&str>>().join("\n"))
+    );
+}
+
+#[test]
+fn test_operations() {
+    let parse = |text| parse_operation(1, text);
+    assert_eq!(Ok(Some(14)), parse("old + old").map(|o| o.value(7)));
+    assert_eq!(Ok(Some(15)), parse("old * 2 + 1").map(|o| o.value(7)));
+    assert_eq!(Ok(Some(29)), parse("1+old*(old-3)").map(|o| o.value(7)));
+    assert_eq!(Ok(Some(2)), parse("old - 3 - 2").map(|o| o.value(7)));
+    assert_eq!(Ok(None), parse("old - 8").map(|o| o.value(7)));
+    assert_eq!(
+        Ok(None),
+        parse("old - 8").map(|o| o.value_exact(&BigUint::from(7u64)))
+    );
+    assert_eq!(
+        Ok(Some(BigUint::from(u64::MAX) * BigUint::from(u64::MAX))),
+        parse("old * old").map(|o| o.value_exact(&BigUint::from(u64::MAX)))
+    );
+    assert_eq!(Ok(None), parse("old * old").map(|o| o.value(1 << 32)));
+    for text in [
+        "old +",
+        "old / 2",
+        "new * 2",
+        "(old + 1",
+        "old old",
+        "99999999999999999999",
+    ] {
+        assert_eq!(
+            Err(NotesError::UnsupportedOperation {
+                line: 1,
+                text: text.to_string()
+            }),
+            parse(text)
+        );
+    }
+
+    // Reducing modulo the LCM gives the same result as reducing the exact value
+    let operation = parse("(old + 3) * old + 2 * (old + 1)").unwrap();
+    for old in [3, 10, 97, 12345] {
+        let exact = operation.value(old).unwrap();
+        assert_eq!(Some(exact % 221), operation.value_modulo(old, 221));
+        assert_eq!(
+            Some(exact % 221),
+            operation.value_modulo(old + 221 * 5, 221)
+        );
+    }
+    assert_eq!(None, parse("old * 2 - 1").unwrap().value_modulo(10, 221));
+    assert_eq!(
+        Some((u64::MAX as u128 * u64::MAX as u128 % 1_000_000_007) as u64),
+        parse("old * old")
+            .unwrap()
+            .value_modulo(u64::MAX, 1_000_000_007)
+    );
+
+    let monkeys = parse_monkeys(&include_str!("../example.txt").replacen(
+        "old * 19",
+        "old * old * old * old * old",
+        1,
+    ))
+    .unwrap();
+    assert_eq!(
+        Err(GameError::WorryOverflow {
+            round: 3,
+            monkey: 0,
+            worry_level: "113965054".to_string(),
+        }),
+        part_1(monkeys.clone()).map(|_| ())
+    );
+    assert!(part_2(monkeys).is_ok());
+
+    // Part 1 notices the worry level going negative, part 2 refuses to reduce it at all
+    let monkeys =
+        parse_monkeys(&include_str!("../example.txt").replacen("old * 19", "old - 80", 1)).unwrap();
+    assert_eq!(
+        Err(GameError::WorryOverflow {
+            round: 1,
+            monkey: 0,
+            worry_level: "79".to_string(),
+        }),
+        part_1(monkeys.clone()).map(|_| ())
+    );
+    assert_eq!(
+        Err(GameError::IncompatibleOperation { monkey: 0 }),
+        part_2(monkeys)
+    );
+    assert_eq!(Err(GameError::NoMonkeys), part_2(vec![]));
+
+    // The tests have to share a multiple that fits in a u64
+    let monkeys = parse_monkeys(
+        &include_str!("../example.txt")
+            .replacen("divisible by 23", "divisible by 4294967311", 1)
+            .replacen("divisible by 19", "divisible by 4294967357", 1),
+    )
+    .unwrap();
+    assert_eq!(Err(GameError::ModulusOverflow), part_2(monkeys));
+    assert_eq!(Some(12), lcm(4, 6));
+    assert_eq!(Some(u64::MAX), lcm(u64::MAX, u64::MAX));
+    assert_eq!(None, lcm(u64::MAX, 2));
+}
+
+#[test]
+fn test_recording() {
+    let monkeys = parse_monkeys(include_str!("../example.txt")).unwrap();
+    let recording = Recording {
+        snapshot_rounds: vec![1, 20],
+        trace: true,
+    };
+    let simulation = simulate(monkeys, 20, &recording, |operation, &old| {
+        operation.value(old).map(|value| value / 3)
+    })
+    .unwrap();
+    assert_eq!(vec![101, 95, 7, 105], simulation.inspections);
+    assert_eq!(
+        Snapshot {
+            round: 1,
+            items: vec![
+                vec![20, 23, 27, 26],
+                vec![2080, 25, 167, 207, 401, 1046],
+                vec![],
+                vec![],
+            ],
+            inspections: vec![2, 4, 3, 5],
+        },
+        simulation.snapshots[0]
+    );
+    assert_eq!(
+        [
+            "After round 20 the monkeys are holding items with these worry levels:",
+            "Monkey 0: 10, 12, 14, 26, 34",
+            "Monkey 1: 245, 93, 53, 199, 115",
+            "Monkey 2: ",
+            "Monkey 3: ",
+            "Monkey 0 inspected items 101 times.",
+            "Monkey 1 inspected items 95 times.",
+            "Monkey 2 inspected items 7 times.",
+            "Monkey 3 inspected items 105 times.",
+        ]
+        .join("\n"),
+        render_snapshot(&simulation.snapshots[1])
+    );
+    assert_eq!(
+        [
+            " Round  Monkey 0  Monkey 1  Monkey 2  Monkey 3",
+            "     1         2         4         3         5",
+            "    20       101        95         7       105",
+        ]
+        .join("\n"),
+        inspection_history(&simulation.snapshots)
+    );
+
+    // Every inspection is a throw
+    assert_eq!(308, simulation.trace.len());
+    assert_eq!(
+        [
+            "round,item,from,to,worry_level",
+            "1,0,0,3,500",
+            "1,1,0,3,620",
+            "1,2,1,0,20",
+        ]
+        .join("\n"),
+        trace_csv(&simulation.trace[..3])
+    );
+    assert_eq!(
+        [
+            "[",
+            "  {\"round\": 1, \"item\": 0, \"from\": 0, \"to\": 3, \"worry_level\": 500},",
+            "  {\"round\": 1, \"item\": 1, \"from\": 0, \"to\": 3, \"worry_level\": 620}",
+            "]",
+        ]
+        .join("\n"),
+        trace_json(&simulation.trace[..2])
+    );
+    assert_eq!("[]", trace_json::<u64>(&[]));
+}
+
+#[test]
+fn test_rules() {
+    let monkeys = parse_monkeys(include_str!("../example.txt")).unwrap();
+    let rules = |relief, metric| Rules {
+        round_count: 20,
+        relief,
+        metric,
+    };
+    let recording = Recording::default();
+
+    // Exact worry levels and the reduced ones lead to the same throws
+    let exact = play(
+        monkeys.clone(),
+        &rules(Relief::None, Metric::Sum),
+        &recording,
+    )
+    .unwrap();
+    assert!(matches!(exact, Played::Exact(_)));
+    assert_eq!(&[99, 97, 8, 103], exact.inspections());
+    let reduced = play(
+        monkeys.clone(),
+        &rules(Relief::Modulo, Metric::Sum),
+        &recording,
+    )
+    .unwrap();
+    assert!(matches!(reduced, Played::Bounded(_)));
+    assert_eq!(&[99, 97, 8, 103], reduced.inspections());
+
+    for (metric, expected) in [
+        (Metric::TopProduct(2), 10197),
+        (Metric::TopProduct(3), 10197 * 97),
+        (Metric::TopSum(2), 202),
+        (Metric::Sum, 307),
+    ] {
+        assert_eq!(
+            Ok(expected),
+            monkey_business(monkeys.clone(), &rules(Relief::None, metric))
+        );
+    }
+    assert_eq!(
+        Ok(10605),
+        monkey_business(
+            monkeys.clone(),
+            &rules(Relief::custom(|w| Some(w / 3u32)), Metric::TopProduct(2))
+        )
+    );
+    assert_eq!(
+        Ok(10605),
+        monkey_business(
+            monkeys.clone(),
+            &rules(Relief::Divide(3), Metric::TopProduct(2))
+        )
+    );
+    assert_eq!(None, Metric::TopProduct(2).score(&[u64::MAX, 2]));
+    assert_eq!(Some(0), Metric::TopSum(3).score(&[]));
+
+    assert!(matches!(parse_relief("divide:7"), Some(Relief::Divide(7))));
+    assert!(parse_relief("divide:0").is_none());
+    let unrelieved = parse_relief("custom:old").unwrap();
+    assert_eq!(
+        Ok(10197),
+        monkey_business(monkeys, &rules(unrelieved, Metric::TopProduct(2)))
+    );
+    assert!(parse_relief("custom:old +").is_none());
+    assert!(matches!(parse_metric("top-sum:3"), Some(Metric::TopSum(3))));
+    assert!(parse_metric("max").is_none());
 }